@@ -1,3 +1,4 @@
+use crate::span::{SourceMap, SpanContext};
 use crate::Span;
 use colorful::{Color, Colorful};
 use std::fmt::{Display, Formatter};
@@ -39,30 +40,102 @@ impl AssemblerMessageType {
 }
 
 impl AssemblerMessage {
-    pub fn write(self, source_path: &path::Path, code: &str) -> String {
+    pub fn write(self, source_map: &SourceMap) -> String {
         let title = format!(
             "{}{} {}",
             self.msg_type.to_string().bold(),
             ":".bold(),
             self.description.bold()
         );
-        let file = format!("  {} {}", "-->".blue().bold(), source_path.display());
-        let span = if let Some(span) = self.span {
-            span.to_string()
-        } else {
-            String::from("1:1")
-        };
-        let context = if let Some(span) = self.span {
-            write_context(code, span, self.msg_type)
-        } else {
-            String::new()
+        let span = match self.span {
+            Some(span) => span,
+            None => {
+                let (path, _) = source_map.get_file(0);
+                return format!("{}\n{}:1:1\n", title, write_file(path));
+            }
         };
 
-        format!("{}\n{}:{}\n{}", title, file, span, context)
+        let mut message = format!(
+            "{}\n{}",
+            title,
+            write_location(source_map, span, self.msg_type.get_color())
+        );
+
+        // Also point to the macro invocations, repetitions and includes that brought the code here.
+        // Recursive macros repeat the same note, so those are only written once with a count.
+        let mut notes: Vec<(String, String, usize)> = Vec::new();
+        let mut context = span.context;
+        loop {
+            let (note, site) = match source_map.get_context(context) {
+                SpanContext::Macro { name, site, .. } => {
+                    (format!("in expansion of macro {}", name), site)
+                }
+                SpanContext::Repetition {
+                    directive,
+                    iteration,
+                    count,
+                    site,
+                    ..
+                } => (
+                    format!(
+                        "in iteration {} of {} of this {}",
+                        iteration, count, directive
+                    ),
+                    site,
+                ),
+                SpanContext::File {
+                    included_at: Some(site),
+                    ..
+                } => (String::from("in the file included here"), site),
+                SpanContext::File { .. } => break,
+            };
+            let location = write_location(source_map, *site, Color::Blue);
+            match notes.last_mut() {
+                Some((last_note, last_location, count))
+                    if *last_note == note && *last_location == location =>
+                {
+                    *count += 1
+                }
+                _ => notes.push((note, location, 1)),
+            }
+            context = site.context;
+        }
+
+        for (note, location, count) in notes {
+            let repetitions = if count > 1 {
+                format!(" ({} times)", count)
+            } else {
+                String::new()
+            };
+            message += &format!(
+                "\n{}{} {}{}\n{}",
+                "note".blue().bold(),
+                ":".bold(),
+                note,
+                repetitions,
+                location
+            );
+        }
+
+        message
     }
 }
 
-fn write_context(code: &str, span: Span, msg_type: AssemblerMessageType) -> String {
+fn write_file(path: &path::Path) -> String {
+    format!("  {} {}", "-->".blue().bold(), path.display())
+}
+
+fn write_location(source_map: &SourceMap, span: Span, color: Color) -> String {
+    let (path, code) = source_map.get_file(span.context);
+    format!(
+        "{}:{}\n{}",
+        write_file(path),
+        span,
+        write_context(code, span, color)
+    )
+}
+
+fn write_context(code: &str, span: Span, color: Color) -> String {
     let iter = code.chars().peekable();
     let mut line = 1;
     let mut context = String::new();
@@ -79,7 +152,6 @@ fn write_context(code: &str, span: Span, msg_type: AssemblerMessageType) -> Stri
         "{}\n{}{}",
         context,
         " ".repeat(span.lo.col - 1),
-        "^".repeat(span.lo.col.abs_diff(span.hi.col))
-            .color(msg_type.get_color())
+        "^".repeat(span.lo.col.abs_diff(span.hi.col)).color(color)
     )
 }
@@ -1,5 +1,7 @@
 use crate::assembler::message::{AssemblerMessage, AssemblerMessageType};
-use crate::{Flags, Lexer, MachineCodeGenerator, Parser, SymbolTableBuilder};
+use crate::span::SourceMap;
+use crate::symbol_table::SymbolTable;
+use crate::{Flags, Lexer, MachineCodeGenerator, Parser, Preprocessor, Span, SymbolTableBuilder};
 
 pub mod message;
 
@@ -8,23 +10,84 @@ pub struct Assembler<'a> {
 }
 
 pub struct AssemblerResult {
-    pub machine_code: Option<Vec<u8>>,
+    pub program: Option<AssembledProgram>,
     pub assembler_messages: Vec<AssemblerMessage>,
 }
 
+pub struct AssembledProgram {
+    pub machine_code: Vec<u8>,
+    pub symbol_table: SymbolTable,
+    pub fragments: Vec<Fragment>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// A piece of machine code generated by a single statement
+#[derive(Clone)]
+pub struct Fragment {
+    pub address: u16,
+    pub length: u32,
+    pub span: Span,
+    pub kind: FragmentKind,
+}
+
+#[derive(PartialEq, Eq, Clone)]
+pub enum FragmentKind {
+    Instruction,
+    Data,
+    Label(String),
+}
+
+/// A field of the machine code whose value depends on the address of a symbol
+#[derive(Clone)]
+pub struct Relocation {
+    pub address: u16,
+    pub field: RelocationField,
+    pub kind: RelocationKind,
+    pub symbol: String,
+    pub addend: u16,
+}
+
+/// Where the relocated value is stored
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RelocationField {
+    Byte,
+    Word,
+    Imm6,
+    Imm8,
+}
+
+/// How the relocated value is computed from the symbol address plus the addend
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RelocationKind {
+    Absolute,
+    Lo,
+    Hi,
+    PcRelative,
+}
+
 impl<'a> Assembler<'a> {
     pub fn new(flags: &'a Flags) -> Self {
         Self { flags }
     }
 
-    pub fn assemble(&self, code: &str) -> AssemblerResult {
+    pub fn assemble(&self, source_map: &mut SourceMap) -> AssemblerResult {
         let mut result = AssemblerResult {
-            machine_code: None,
+            program: None,
             assembler_messages: Vec::new(),
         };
 
-        let lexer = Lexer::new(code);
-        let parser = Parser::new(lexer);
+        let (_, code) = source_map.get_file(0);
+        let tokens: Vec<_> = Lexer::new(code).collect();
+        let mut preprocessor = Preprocessor::new(source_map, self.flags);
+        let tokens = preprocessor.preprocess(tokens);
+        result
+            .assembler_messages
+            .extend(preprocessor.get_messages());
+        if !result.assembler_messages.is_empty() {
+            return result;
+        }
+
+        let parser = Parser::new(tokens.into_iter());
         let node = match parser.parse() {
             Ok(n) => n,
             Err(e) => {
@@ -41,17 +104,32 @@ impl<'a> Assembler<'a> {
         let symbol_table = symbol_table_builder.get_symbol_table();
 
         let mut machine_code_generator = MachineCodeGenerator::new(&symbol_table, self.flags);
-        result.machine_code = machine_code_generator.generate(&node);
+        let machine_code = machine_code_generator.generate(&node);
         result
             .assembler_messages
             .extend(machine_code_generator.get_messages());
 
-        if result
+        if !result
             .assembler_messages
             .iter()
             .any(|msg| msg.msg_type == AssemblerMessageType::Error)
         {
-            result.machine_code = None;
+            // Outputs refer to source files, so expanded code is attributed to its invocation
+            let fragments = machine_code_generator
+                .get_fragments()
+                .into_iter()
+                .map(|fragment| Fragment {
+                    span: source_map.get_file_span(fragment.span),
+                    ..fragment
+                })
+                .collect();
+            let relocations = machine_code_generator.get_relocations();
+            result.program = machine_code.map(|machine_code| AssembledProgram {
+                machine_code,
+                symbol_table,
+                fragments,
+                relocations,
+            });
         }
 
         result
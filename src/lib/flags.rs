@@ -1,8 +1,22 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
 pub struct Flags {
     pub text_section_start: u16,
     pub data_section_start: DataSectionStart,
     pub auto_align_words: bool,
     pub auto_align_sections: bool,
+    pub memory_width: Option<MemoryWidth>,
+    pub byte_order: ByteOrder,
+    pub memory_depth: Option<u32>,
+    pub fill_byte: u8,
+    pub annotate: bool,
+    pub relocatable: bool,
+    /// Constants defined from the command line
+    pub defines: HashMap<String, i32>,
+    /// Where to look for included files that aren't next to the file including them
+    pub include_paths: Vec<PathBuf>,
 }
 
 pub enum DataSectionStart {
@@ -10,6 +24,43 @@ pub enum DataSectionStart {
     Absolute(u16),
 }
 
+pub struct Output {
+    pub path: PathBuf,
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OutputFormat {
+    Binary,
+    Sections,
+    IntelHex,
+    SRecord,
+    Mif,
+    Readmemh,
+    Vhdl,
+    Logisim,
+    C,
+    Rust,
+    Listing,
+    Map,
+    DebugInfo,
+    HexWords,
+    Bits,
+    Object,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MemoryWidth {
+    Byte,
+    Word,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
 impl Default for Flags {
     fn default() -> Self {
         Self {
@@ -17,6 +68,129 @@ impl Default for Flags {
             data_section_start: DataSectionStart::AfterText,
             auto_align_words: false,
             auto_align_sections: false,
+            memory_width: None,
+            byte_order: ByteOrder::Little,
+            memory_depth: None,
+            fill_byte: 0,
+            annotate: false,
+            relocatable: false,
+            defines: HashMap::new(),
+            include_paths: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bin" => Ok(OutputFormat::Binary),
+            "sections" => Ok(OutputFormat::Sections),
+            "ihex" => Ok(OutputFormat::IntelHex),
+            "srec" => Ok(OutputFormat::SRecord),
+            "mif" => Ok(OutputFormat::Mif),
+            "readmemh" => Ok(OutputFormat::Readmemh),
+            "vhdl" => Ok(OutputFormat::Vhdl),
+            "logisim" => Ok(OutputFormat::Logisim),
+            "c" => Ok(OutputFormat::C),
+            "rust" => Ok(OutputFormat::Rust),
+            "listing" => Ok(OutputFormat::Listing),
+            "map" => Ok(OutputFormat::Map),
+            "json" => Ok(OutputFormat::DebugInfo),
+            "hexwords" => Ok(OutputFormat::HexWords),
+            "bits" => Ok(OutputFormat::Bits),
+            "obj" => Ok(OutputFormat::Object),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Infers the format from the extension of the output file, if it's a known one
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        Some(match path.extension().and_then(|e| e.to_str()) {
+            Some("hex" | "ihex") => OutputFormat::IntelHex,
+            Some("srec" | "s19") => OutputFormat::SRecord,
+            Some("mif") => OutputFormat::Mif,
+            Some("memh") => OutputFormat::Readmemh,
+            Some("vhd" | "vhdl") => OutputFormat::Vhdl,
+            Some("h") => OutputFormat::C,
+            Some("rs") => OutputFormat::Rust,
+            Some("lst") => OutputFormat::Listing,
+            Some("map") => OutputFormat::Map,
+            Some("json") => OutputFormat::DebugInfo,
+            Some("o" | "obj") => OutputFormat::Object,
+            _ => return None,
+        })
+    }
+
+    /// The memory width used when the user doesn't specify one
+    pub fn get_default_memory_width(&self) -> MemoryWidth {
+        match self {
+            OutputFormat::Mif
+            | OutputFormat::Readmemh
+            | OutputFormat::Vhdl
+            | OutputFormat::Logisim
+            | OutputFormat::HexWords
+            | OutputFormat::Bits => MemoryWidth::Word,
+            _ => MemoryWidth::Byte,
+        }
+    }
+}
+
+impl FromStr for MemoryWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(MemoryWidth::Byte),
+            "16" => Ok(MemoryWidth::Word),
+            _ => Err(format!("Unsupported memory width: {} (use 8 or 16)", s)),
+        }
+    }
+}
+
+impl MemoryWidth {
+    pub fn get_bits(&self) -> u32 {
+        match self {
+            MemoryWidth::Byte => 8,
+            MemoryWidth::Word => 16,
+        }
+    }
+
+    pub fn get_bytes(&self) -> usize {
+        match self {
+            MemoryWidth::Byte => 1,
+            MemoryWidth::Word => 2,
+        }
+    }
+}
+
+impl FromStr for ByteOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "little" => Ok(ByteOrder::Little),
+            "big" => Ok(ByteOrder::Big),
+            _ => Err(format!("Unsupported byte order: {} (use little or big)", s)),
+        }
+    }
+}
+
+impl ByteOrder {
+    pub fn to_bytes(&self, word: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Little => word.to_le_bytes(),
+            ByteOrder::Big => word.to_be_bytes(),
+        }
+    }
+
+    pub fn from_bytes(&self, bytes: [u8; 2]) -> u16 {
+        match self {
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+            ByteOrder::Big => u16::from_be_bytes(bytes),
         }
     }
 }
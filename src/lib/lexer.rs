@@ -8,6 +8,7 @@ pub struct Lexer<'a> {
     remaining: &'a str,
     cur_line: usize,
     cur_col: usize,
+    context: usize,
 }
 
 impl<'a> Lexer<'a> {
@@ -26,8 +27,43 @@ impl<'a> Lexer<'a> {
         r"\.even" => Even,
 
         r"\.set" => Set,
+        r"\.globa?l" => Globl,
+        r"\.macro" => Macro,
+        r"\.endm" => Endm,
+        r"\.if" => If,
+        r"\.ifdef" => Ifdef,
+        r"\.ifndef" => Ifndef,
+        r"\.elseif" => Elseif,
+        r"\.else" => Else,
+        r"\.endif" => Endif,
+        r"\.include" => Include,
+        r"\.incbin" => Incbin,
+        r"\.rept" => Rept,
+        r"\.irp" => Irp,
+        r"\.irpc" => Irpc,
+        r"\.endr" => Endr,
+        r"\.ascii" => Ascii,
+        r"\.asciz" => Asciz,
+        r"\.string" => Asciz,
 
         r"," => Comma,
+        r"\+" => Plus,
+        r"-" => Minus,
+        r"\*" => Star,
+        r"/" => Slash,
+        r"%" => Percent,
+        r"<<" => ShiftLeft,
+        r">>" => ShiftRight,
+        r"\&" => Ampersand,
+        r"\|" => Pipe,
+        r"\^" => Caret,
+        r"\~" => Tilde,
+        r"==" => DoubleEquals,
+        r"!=" => NotEquals,
+        r"<" => Less,
+        r"<=" => LessEquals,
+        r">" => Greater,
+        r">=" => GreaterEquals,
         r"\(" => Lpar,
         r"\)" => Rpar,
         r":" => Colon,
@@ -60,17 +96,21 @@ impl<'a> Lexer<'a> {
         r"OUT" => Out,
         r"NOP" => Nop,
 
+        r#""([^"\\\n]|\\[^\n])*""# => parse_str(tok),
+        r#""([^"\\\n]|\\[^\n])*"# => Malformed(String::from("Unterminated string")),
+        r#"'([^'\\\n]|\\[^\n])*'"# => parse_char_lit(tok),
+        r#"'([^'\\\n]|\\[^\n])*"# => Malformed(String::from("Unterminated character literal")),
+
         r"R[0-7]" => parse_reg(tok),
-        r"[0-9]+" => parse_int_lit(tok),
-        r"[-+][0-9]+" => parse_int_lit(tok),
-        r"0(x|X)[0-9a-fA-f]+" => parse_hex_lit(tok),
+        // Trailing letters are part of the literal so that 0b102 is reported as a bad literal
+        r"[0-9][0-9a-zA-Z\_]*" => parse_int_lit(tok),
 
         r"lo" => Lo,
         r"hi" => Hi,
 
-        r"[a-zA-Z][a-zA-Z0-9\_\-]*" => Ident(tok.into()),
+        r"[a-zA-Z][a-zA-Z0-9\_]*" => Ident(tok.into()),
 
-        r"[\n\t\s\r ]+" => Ignore,
+        r"[\n\t\r ]+" => Ignore,
         r"." => Invalid(tok.into())
     }
 
@@ -80,8 +120,15 @@ impl<'a> Lexer<'a> {
             remaining: input,
             cur_line: 1,
             cur_col: 1,
+            context: 0,
         }
     }
+
+    /// Makes the spans of the tokens belong to a context other than the main source file
+    pub fn in_context(mut self, context: usize) -> Self {
+        self.context = context;
+        self
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -116,10 +163,6 @@ impl<'a> Iterator for Lexer<'a> {
             } else {
                 self.remaining = remaining;
 
-                if let Token::End = token {
-                    self.remaining = "";
-                }
-
                 return Some((
                     token,
                     Span {
@@ -128,6 +171,7 @@ impl<'a> Iterator for Lexer<'a> {
                             line: self.cur_line,
                             col: self.cur_col,
                         },
+                        context: self.context,
                     },
                 ));
             }
@@ -138,7 +182,7 @@ impl<'a> Iterator for Lexer<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::assert_matches::assert_matches;
+    use std::assert_matches;
 
     #[test]
     fn lex_instruction() {
@@ -187,6 +231,16 @@ mod tests {
         assert_matches!(lexer.next(), None);
     }
 
+    #[test]
+    fn lex_ident_after_whitespace() {
+        let mut lexer = Lexer::new("\nstart: lo(start)");
+
+        match lexer.next() {
+            Some((Token::Ident(name), _)) => assert_eq!(name, "start"),
+            _ => panic!(),
+        }
+    }
+
     #[test]
     fn lex_functions() {
         let mut lexer = Lexer::new("lo(0) hi(0)");
@@ -202,6 +256,32 @@ mod tests {
         assert_matches!(lexer.next(), None);
     }
 
+    #[test]
+    fn lex_operators() {
+        let mut lexer = Lexer::new("-(SIZE-1) << 2 + ~lo(x)*3");
+
+        assert_matches!(lexer.next(), Some((Token::Minus, _)));
+        assert_matches!(lexer.next(), Some((Token::Lpar, _)));
+        match lexer.next() {
+            Some((Token::Ident(name), _)) => assert_eq!(name, "SIZE"),
+            _ => panic!(),
+        }
+        assert_matches!(lexer.next(), Some((Token::Minus, _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(1), _)));
+        assert_matches!(lexer.next(), Some((Token::Rpar, _)));
+        assert_matches!(lexer.next(), Some((Token::ShiftLeft, _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(2), _)));
+        assert_matches!(lexer.next(), Some((Token::Plus, _)));
+        assert_matches!(lexer.next(), Some((Token::Tilde, _)));
+        assert_matches!(lexer.next(), Some((Token::Lo, _)));
+        assert_matches!(lexer.next(), Some((Token::Lpar, _)));
+        assert_matches!(lexer.next(), Some((Token::Ident(_), _)));
+        assert_matches!(lexer.next(), Some((Token::Rpar, _)));
+        assert_matches!(lexer.next(), Some((Token::Star, _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(3), _)));
+        assert_matches!(lexer.next(), None);
+    }
+
     #[test]
     fn lex_directives() {
         let mut lexer = Lexer::new(".text .data .byte .word .space .even .end .text");
@@ -213,6 +293,100 @@ mod tests {
         assert_matches!(lexer.next(), Some((Token::Space, _)));
         assert_matches!(lexer.next(), Some((Token::Even, _)));
         assert_matches!(lexer.next(), Some((Token::End, _)));
+        assert_matches!(lexer.next(), Some((Token::BeginText, _)));
+        assert_matches!(lexer.next(), None);
+    }
+
+    #[test]
+    fn lex_macro_directives() {
+        let mut lexer = Lexer::new(".macro .endm .end");
+
+        assert_matches!(lexer.next(), Some((Token::Macro, _)));
+        assert_matches!(lexer.next(), Some((Token::Endm, _)));
+        assert_matches!(lexer.next(), Some((Token::End, _)));
+        assert_matches!(lexer.next(), None);
+    }
+
+    #[test]
+    fn lex_conditional_directives() {
+        let mut lexer = Lexer::new(".if .ifdef .ifndef .elseif .else .endif");
+
+        assert_matches!(lexer.next(), Some((Token::If, _)));
+        assert_matches!(lexer.next(), Some((Token::Ifdef, _)));
+        assert_matches!(lexer.next(), Some((Token::Ifndef, _)));
+        assert_matches!(lexer.next(), Some((Token::Elseif, _)));
+        assert_matches!(lexer.next(), Some((Token::Else, _)));
+        assert_matches!(lexer.next(), Some((Token::Endif, _)));
+        assert_matches!(lexer.next(), None);
+    }
+
+    #[test]
+    fn lex_repetition_directives() {
+        let mut lexer = Lexer::new(".rept .irp .irpc .endr");
+
+        assert_matches!(lexer.next(), Some((Token::Rept, _)));
+        assert_matches!(lexer.next(), Some((Token::Irp, _)));
+        assert_matches!(lexer.next(), Some((Token::Irpc, _)));
+        assert_matches!(lexer.next(), Some((Token::Endr, _)));
+        assert_matches!(lexer.next(), None);
+    }
+
+    #[test]
+    fn lex_include() {
+        let mut lexer = Lexer::new(".include \"lib/macros.S\" .incbin \"font.bin\"");
+
+        assert_matches!(lexer.next(), Some((Token::Include, _)));
+        match lexer.next() {
+            Some((Token::Str(path), _)) => assert_eq!(path, b"lib/macros.S"),
+            _ => panic!(),
+        }
+        assert_matches!(lexer.next(), Some((Token::Incbin, _)));
+        assert_matches!(lexer.next(), Some((Token::Str(_), _)));
+        assert_matches!(lexer.next(), None);
+    }
+
+    #[test]
+    fn lex_numbers() {
+        let mut lexer = Lexer::new("42 0xBEEF 0o777 0b1010_0101 65_535 65536 0b102 0x 7up");
+
+        assert_matches!(lexer.next(), Some((Token::Lit(42), _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(0xBEEF), _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(0o777), _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(0b1010_0101), _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(65535), _)));
+        assert_matches!(lexer.next(), Some((Token::Malformed(_), _)));
+        assert_matches!(lexer.next(), Some((Token::Malformed(_), _)));
+        assert_matches!(lexer.next(), Some((Token::Malformed(_), _)));
+        assert_matches!(lexer.next(), Some((Token::Malformed(_), _)));
+        assert_matches!(lexer.next(), None);
+    }
+
+    #[test]
+    fn lex_char_literals() {
+        let mut lexer = Lexer::new(r"'a' '\n' '\x7F' '\'' 'ab' '\q'");
+
+        assert_matches!(lexer.next(), Some((Token::Lit(0x61), _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(0x0A), _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(0x7F), _)));
+        assert_matches!(lexer.next(), Some((Token::Lit(0x27), _)));
+        assert_matches!(lexer.next(), Some((Token::Malformed(_), _)));
+        assert_matches!(lexer.next(), Some((Token::Malformed(_), _)));
+        assert_matches!(lexer.next(), None);
+    }
+
+    #[test]
+    fn lex_strings() {
+        let mut lexer = Lexer::new(r#".ascii "a\"b\x00" .asciz "" .string "unterminated"#);
+
+        assert_matches!(lexer.next(), Some((Token::Ascii, _)));
+        match lexer.next() {
+            Some((Token::Str(bytes), _)) => assert_eq!(bytes, b"a\"b\0"),
+            _ => panic!(),
+        }
+        assert_matches!(lexer.next(), Some((Token::Asciz, _)));
+        assert_matches!(lexer.next(), Some((Token::Str(_), _)));
+        assert_matches!(lexer.next(), Some((Token::Asciz, _)));
+        assert_matches!(lexer.next(), Some((Token::Malformed(_), _)));
         assert_matches!(lexer.next(), None);
     }
 
@@ -226,7 +400,8 @@ mod tests {
                 _,
                 Span {
                     lo: FileLoc { col: 2, line: 2 },
-                    hi: FileLoc { col: 6, line: 2 }
+                    hi: FileLoc { col: 6, line: 2 },
+                    context: 0
                 }
             ))
         );
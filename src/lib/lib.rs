@@ -1,10 +1,10 @@
-#![feature(assert_matches)]
-
 mod assembler;
 mod flags;
 mod lexer;
 mod nodes;
+mod output;
 mod parser;
+mod preprocessor;
 mod span;
 mod symbol_table;
 mod tokens;
@@ -12,43 +12,55 @@ mod visitors;
 
 use crate::lexer::Lexer;
 use crate::parser::Parser;
+use crate::preprocessor::Preprocessor;
 use crate::visitors::{MachineCodeGenerator, SymbolTableBuilder};
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::{fs, path};
 
 use crate::assembler::message::{AssemblerMessage, AssemblerMessageType};
 use crate::assembler::Assembler;
-use crate::span::Span;
+use crate::span::{SourceMap, Span};
 pub use flags::*;
 
 pub fn assemble(
     source_file: &path::Path,
-    output_file: &path::Path,
+    outputs: &[Output],
     flags: &Flags,
 ) -> Result<String, String> {
     let code = read_source(source_file)?;
+    let source_file = if is_std_stream(source_file) {
+        path::Path::new("<stdin>")
+    } else {
+        source_file
+    };
 
+    let mut source_map = SourceMap::new(source_file, code);
     let assembler = Assembler::new(flags);
-    let asm_result = assembler.assemble(&code);
+    let asm_result = assembler.assemble(&mut source_map);
 
-    if let Some(machine_code) = asm_result.machine_code {
-        write_output(output_file, machine_code.as_slice())?;
-        Ok(write_messages(
-            asm_result.assembler_messages,
-            source_file,
-            &code,
-        ))
+    if let Some(program) = asm_result.program {
+        for output in outputs {
+            for file in output::generate(&program, &source_map, output, flags)? {
+                write_output(&file.path, &file.contents)?;
+            }
+        }
+        Ok(write_messages(asm_result.assembler_messages, &source_map))
     } else {
-        Err(write_messages(
-            asm_result.assembler_messages,
-            source_file,
-            &code,
-        ))
+        Err(write_messages(asm_result.assembler_messages, &source_map))
     }
 }
 
-fn read_source(path: &path::Path) -> Result<String, String> {
-    let mut file = fs::File::open(path).map_err(|e| format!("Error opening source file: {}", e))?;
+/// Checks if the path refers to stdin or stdout (`-`)
+fn is_std_stream(path: &path::Path) -> bool {
+    path == path::Path::new("-")
+}
+
+pub(crate) fn read_source(path: &path::Path) -> Result<String, String> {
+    let mut file: Box<dyn Read> = if is_std_stream(path) {
+        Box::new(io::stdin())
+    } else {
+        Box::new(fs::File::open(path).map_err(|e| format!("Error opening source file: {}", e))?)
+    };
 
     let mut code = String::new();
     file.read_to_string(&mut code)
@@ -58,18 +70,22 @@ fn read_source(path: &path::Path) -> Result<String, String> {
 }
 
 fn write_output(path: &path::Path, data: &[u8]) -> Result<(), String> {
-    let mut file =
-        fs::File::create(path).map_err(|e| format!("Error creating output file: {}", e))?;
+    let mut file: Box<dyn Write> = if is_std_stream(path) {
+        Box::new(io::stdout())
+    } else {
+        Box::new(fs::File::create(path).map_err(|e| format!("Error creating output file: {}", e))?)
+    };
 
     file.write_all(data)
+        .and_then(|_| file.flush())
         .map_err(|e| format!("Error writing output file: {}", e))
 }
 
-fn write_messages(messages: Vec<AssemblerMessage>, source_path: &path::Path, code: &str) -> String {
+fn write_messages(messages: Vec<AssemblerMessage>, source_map: &SourceMap) -> String {
     let mut ret = String::new();
 
     for msg in messages {
-        ret += format!("{}\n", msg.write(source_path, code)).as_str();
+        ret += format!("{}\n", msg.write(source_map)).as_str();
     }
 
     ret
@@ -1,3 +1,4 @@
+use crate::symbol_table::SectionId;
 use crate::Span;
 use easy_nodes::{node_system, Node};
 
@@ -14,7 +15,9 @@ node_system! {
         fn visit_label<Label>();
         fn visit_symbol_ref<SymbolRef>();
         fn visit_function<Function>();
+        fn visit_expression<Expression>();
         fn visit_constant<Constant>();
+        fn visit_global<Global>();
     }
 
     #[consumer = visit_program()]
@@ -22,6 +25,7 @@ node_system! {
         pub data_section: Option<Node<Span, DataSection>>,
         pub text_section: Option<Node<Span, TextSection>>,
         pub constants: Vec<Node<Span, Constant>>,
+        pub globals: Vec<Node<Span, Global>>,
     }
 
     #[consumer = visit_data_section()]
@@ -40,6 +44,7 @@ node_system! {
         Label(Node<Span, Label>),
         RawData(Node<Span, RawData>),
         Constant(Node<Span, Constant>),
+        Global(Node<Span, Global>),
     }
 
     #[consumer = visit_instruction()]
@@ -77,6 +82,8 @@ node_system! {
         WordAlign,
         Bytes(Vec<Node<Span, Literal>>),
         Words(Vec<Node<Span, Literal>>),
+        Space { size: Node<Span, Literal>, fill: Node<Span, Literal> },
+        Binary(Vec<u8>),
     }
 
     #[consumer = visit_registry()]
@@ -89,6 +96,7 @@ node_system! {
         Constant(u16),
         SymbolRef(Node<Span, SymbolRef>),
         Function(Node<Span, Function>),
+        Expression(Node<Span, Expression>),
     }
 
     #[consumer = visit_label()]
@@ -107,11 +115,48 @@ node_system! {
         Hi(Node<Span, Literal>),
     }
 
+    #[consumer = visit_expression()]
+    pub enum Expression {
+        Unary { op: UnaryOperator, operand: Node<Span, Literal> },
+        Binary { op: BinaryOperator, lhs: Node<Span, Literal>, rhs: Node<Span, Literal> },
+    }
+
     #[consumer = visit_constant()]
     pub struct Constant {
         pub name: String,
         pub value: Node<Span, Literal>
     }
+
+    #[consumer = visit_global()]
+    pub struct Global {
+        pub name: String
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    Xor,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
 }
 
 impl Program {
@@ -120,6 +165,7 @@ impl Program {
             text_section: None,
             data_section: None,
             constants: Vec::new(),
+            globals: Vec::new(),
         }
     }
 }
@@ -137,15 +183,236 @@ impl TextSection {
 }
 
 impl RawData {
-    pub fn get_size(&self, pos: u16) -> u16 {
+    /// Returns the size of the data, or None for .space, whose size is an expression
+    pub fn get_size(&self, pos: u32) -> Option<usize> {
+        match self {
+            RawData::WordAlign => Some(!pos.is_multiple_of(2) as usize),
+            RawData::Bytes(data) => Some(data.len()),
+            RawData::Words(data) => Some(data.len() * 2),
+            RawData::Space { .. } => None,
+            RawData::Binary(data) => Some(data.len()),
+        }
+    }
+
+    /// Returns the bytes of the strings of .ascii, or of .asciz if they are `terminated`
+    pub fn from_strings(strings: Vec<Vec<u8>>, terminated: bool, span: Span) -> Self {
+        RawData::Bytes(
+            strings
+                .into_iter()
+                .flat_map(|mut string| {
+                    if terminated {
+                        string.push(0);
+                    }
+                    string
+                })
+                .map(|byte| Literal::Constant(byte as u16).to_node(span))
+                .collect(),
+        )
+    }
+}
+
+impl Literal {
+    /// Returns the names of every symbol referenced by the literal
+    pub fn get_symbols(&self) -> Vec<&str> {
+        match self {
+            Literal::Constant(_) => Vec::new(),
+            Literal::SymbolRef(sr) => vec![sr.get_data().name.as_str()],
+            Literal::Function(f) => match f.get_data() {
+                Function::Lo(v) | Function::Hi(v) => v.get_data().get_symbols(),
+            },
+            Literal::Expression(e) => match e.get_data() {
+                Expression::Unary { operand, .. } => operand.get_data().get_symbols(),
+                Expression::Binary { lhs, rhs, .. } => {
+                    let mut symbols = lhs.get_data().get_symbols();
+                    symbols.extend(rhs.get_data().get_symbols());
+                    symbols
+                }
+            },
+        }
+    }
+
+    /// Folds the literal into a value, taking the values of its symbols from `symbol`. In
+    /// relocatable objects, lo() and hi() can't be applied to addresses, as only a relocation could
+    /// fix the result after linking.
+    pub fn evaluate<A: AddressValue>(
+        &self,
+        symbol: &impl Fn(&Node<Span, SymbolRef>) -> Result<Value<A>, (String, Span)>,
+        relocatable: bool,
+    ) -> Result<Value<A>, (String, Span)> {
         match self {
-            RawData::WordAlign => (pos % 2 != 0) as u16,
-            RawData::Bytes(data) => data.len() as u16,
-            RawData::Words(data) => data.len() as u16 * 2,
+            Literal::Constant(c) => Ok(Value::Constant(*c as i32)),
+            Literal::SymbolRef(sr) => symbol(sr),
+            Literal::Function(f) => {
+                let (Function::Lo(v) | Function::Hi(v)) = f.get_data();
+                let value = match v.get_data().evaluate(symbol, relocatable)? {
+                    Value::Constant(value) => value as u16,
+                    Value::Address(_) if relocatable => {
+                        return Err((
+                            String::from(
+                                "lo() and hi() of an address can't be used in constants of relocatable objects",
+                            ),
+                            *f.get_common(),
+                        ))
+                    }
+                    Value::Address(address) => address.get_value(),
+                };
+                Ok(Value::Constant(match f.get_data() {
+                    Function::Lo(_) => value & 0xFF,
+                    Function::Hi(_) => value >> 8,
+                } as i32))
+            }
+            Literal::Expression(e) => match e.get_data() {
+                Expression::Unary { op, operand } => op
+                    .apply_value(operand.get_data().evaluate(symbol, relocatable)?)
+                    .map_err(|msg| (msg, *e.get_common())),
+                Expression::Binary { op, lhs, rhs } => op
+                    .apply_value(
+                        lhs.get_data().evaluate(symbol, relocatable)?,
+                        rhs.get_data().evaluate(symbol, relocatable)?,
+                        relocatable,
+                    )
+                    .map_err(|msg| (msg, *e.get_common())),
+            },
+        }
+    }
+
+    pub fn unary(op: UnaryOperator, operand: Node<Span, Literal>, span: Span) -> Node<Span, Self> {
+        Literal::Expression(Expression::Unary { op, operand }.to_node(span)).to_node(span)
+    }
+
+    pub fn binary(
+        op: BinaryOperator,
+        lhs: Node<Span, Literal>,
+        rhs: Node<Span, Literal>,
+        span: Span,
+    ) -> Node<Span, Self> {
+        Literal::Expression(Expression::Binary { op, lhs, rhs }.to_node(span)).to_node(span)
+    }
+}
+
+impl UnaryOperator {
+    pub fn apply(&self, operand: i32) -> Result<i32, String> {
+        check_range(match self {
+            UnaryOperator::Negate => -operand,
+            // Complement the 16 bit value, so ~0xFF00 is 0x00FF
+            UnaryOperator::Not => !(operand as u16) as i32,
+        })
+    }
+
+    pub fn apply_value<A: AddressValue>(&self, operand: Value<A>) -> Result<Value<A>, String> {
+        match operand {
+            Value::Constant(value) => self.apply(value).map(Value::Constant),
+            Value::Address(_) => Err(String::from(
+                "Addresses can only be offset by a constant or subtracted from each other",
+            )),
+        }
+    }
+}
+
+impl BinaryOperator {
+    pub fn apply(&self, lhs: i32, rhs: i32) -> Result<i32, String> {
+        check_range(match self {
+            BinaryOperator::Add => lhs + rhs,
+            BinaryOperator::Sub => lhs - rhs,
+            BinaryOperator::Mul => lhs
+                .checked_mul(rhs)
+                .ok_or_else(|| String::from("This expression overflows 16 bits"))?,
+            BinaryOperator::Div | BinaryOperator::Rem if rhs == 0 => {
+                return Err(String::from("Division by zero"))
+            }
+            BinaryOperator::Div => lhs / rhs,
+            BinaryOperator::Rem => lhs % rhs,
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight if !(0..16).contains(&rhs) => {
+                return Err(format!("Can't shift by {} bits", rhs))
+            }
+            BinaryOperator::ShiftLeft => lhs << rhs,
+            BinaryOperator::ShiftRight => lhs >> rhs,
+            BinaryOperator::And => lhs & rhs,
+            BinaryOperator::Or => lhs | rhs,
+            BinaryOperator::Xor => lhs ^ rhs,
+            BinaryOperator::Equal => (lhs == rhs) as i32,
+            BinaryOperator::NotEqual => (lhs != rhs) as i32,
+            BinaryOperator::Less => (lhs < rhs) as i32,
+            BinaryOperator::LessEqual => (lhs <= rhs) as i32,
+            BinaryOperator::Greater => (lhs > rhs) as i32,
+            BinaryOperator::GreaterEqual => (lhs >= rhs) as i32,
+        })
+    }
+
+    /// Applies the operator following the rules of label arithmetic: an address plus or minus a
+    /// constant is an address, and the difference of two addresses is a constant
+    pub fn apply_value<A: AddressValue>(
+        &self,
+        lhs: Value<A>,
+        rhs: Value<A>,
+        relocatable: bool,
+    ) -> Result<Value<A>, String> {
+        match (self, lhs, rhs) {
+            (_, Value::Constant(lhs), Value::Constant(rhs)) => {
+                self.apply(lhs, rhs).map(Value::Constant)
+            }
+            (BinaryOperator::Add, Value::Address(address), Value::Constant(offset))
+            | (BinaryOperator::Add, Value::Constant(offset), Value::Address(address)) => {
+                offset_address(address, offset).map(Value::Address)
+            }
+            (BinaryOperator::Sub, Value::Address(address), Value::Constant(offset)) => {
+                offset_address(address, -offset).map(Value::Address)
+            }
+            (BinaryOperator::Sub, Value::Address(lhs), Value::Address(rhs)) => {
+                if relocatable
+                    && (lhs.get_section().is_none() || lhs.get_section() != rhs.get_section())
+                {
+                    Err(String::from(
+                        "Only addresses of the same section can be subtracted in a relocatable object",
+                    ))
+                } else {
+                    Ok(Value::Constant(
+                        lhs.get_value() as i32 - rhs.get_value() as i32,
+                    ))
+                }
+            }
+            _ => Err(String::from(
+                "Addresses can only be offset by a constant or subtracted from each other",
+            )),
         }
     }
 }
 
+/// The result of evaluating an expression
+pub enum Value<A> {
+    Constant(i32),
+    Address(A),
+}
+
+/// An address that can take part in expressions
+pub trait AddressValue: Sized {
+    fn get_value(&self) -> u16;
+
+    /// Returns the section of the address, or None if it's an undefined symbol
+    fn get_section(&self) -> Option<SectionId>;
+
+    fn with_offset(self, offset: i32) -> Self;
+}
+
+fn offset_address<A: AddressValue>(address: A, offset: i32) -> Result<A, String> {
+    // Undefined symbols are left for the linker to check
+    if address.get_section().is_some()
+        && !(0..=u16::MAX as i32).contains(&(address.get_value() as i32 + offset))
+    {
+        return Err(String::from("This address is out of the memory range"));
+    }
+    Ok(address.with_offset(offset))
+}
+
+/// Checks that a value fits in 16 bits, either as a signed or as an unsigned number
+fn check_range(value: i32) -> Result<i32, String> {
+    if (i16::MIN as i32..=u16::MAX as i32).contains(&value) {
+        Ok(value)
+    } else {
+        Err(String::from("This expression overflows 16 bits"))
+    }
+}
+
 impl Constant {
     pub fn new(name: String, value: Node<Span, Literal>) -> Self {
         Self { name, value }
@@ -0,0 +1,64 @@
+use crate::assembler::AssembledProgram;
+use crate::output::OutputFile;
+use crate::{Flags, MemoryWidth};
+use std::path;
+
+pub fn generate(program: &AssembledProgram, width: MemoryWidth, flags: &Flags) -> Vec<u8> {
+    // The image always holds whole memory cells
+    let length = program
+        .machine_code
+        .len()
+        .next_multiple_of(width.get_bytes());
+    let mut image = vec![flags.fill_byte; length];
+
+    for section in program.symbol_table.get_sections() {
+        let start = section.get_base_address() as usize;
+        let end = start + section.get_length() as usize;
+        image[start..end].copy_from_slice(&program.machine_code[start..end]);
+    }
+
+    image
+}
+
+/// Writes every non-empty section to its own file, and a manifest with their base addresses
+pub fn generate_sections(program: &AssembledProgram, output_file: &path::Path) -> Vec<OutputFile> {
+    let symbol_table = &program.symbol_table;
+    let stem = output_file
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    let mut manifest = String::from("# section base length file\n");
+    let mut files = Vec::new();
+
+    for (name, section) in [
+        ("text", symbol_table.get_text_section()),
+        ("data", symbol_table.get_data_section()),
+    ] {
+        if section.get_length() == 0 {
+            continue;
+        }
+
+        let path = output_file.with_file_name(format!("{}.{}.bin", stem, name));
+        let start = section.get_base_address() as usize;
+        let end = start + section.get_length() as usize;
+
+        manifest += &format!(
+            ".{} 0x{:04x} 0x{:04x} {}\n",
+            name,
+            section.get_base_address(),
+            section.get_length(),
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        files.push(OutputFile {
+            path,
+            contents: program.machine_code[start..end].to_vec(),
+        });
+    }
+
+    files.push(OutputFile {
+        path: output_file.to_path_buf(),
+        contents: manifest.into_bytes(),
+    });
+    files
+}
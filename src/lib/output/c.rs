@@ -0,0 +1,49 @@
+use crate::assembler::AssembledProgram;
+use crate::output::{cell_list, exported_labels, memory_cells};
+use crate::symbol_table::SectionId;
+use crate::{Flags, MemoryWidth};
+
+pub fn generate(
+    program: &AssembledProgram,
+    width: MemoryWidth,
+    flags: &Flags,
+) -> Result<Vec<u8>, String> {
+    let symbol_table = &program.symbol_table;
+    let cells = memory_cells(&program.machine_code, width, flags.byte_order);
+
+    let mut header = String::from("#ifndef SISA_PROGRAM_H\n#define SISA_PROGRAM_H\n\n");
+    header += "#include <stdint.h>\n";
+
+    for id in [SectionId::Text, SectionId::Data] {
+        let name = id.get_name().trim_start_matches('.');
+        let section = symbol_table.get_section(id);
+        header += &format!(
+            "\n#define SISA_{}_START 0x{:04x}\n#define SISA_{}_LENGTH 0x{:04x}\n",
+            name.to_uppercase(),
+            section.get_base_address(),
+            name.to_uppercase(),
+            section.get_length()
+        );
+
+        // C doesn't allow empty arrays
+        if section.get_length() != 0 {
+            let start = section.get_base_address() as usize / width.get_bytes();
+            let end = (section.get_base_address() as usize + section.get_length() as usize)
+                .div_ceil(width.get_bytes());
+            header += &format!(
+                "static const uint{}_t sisa_{}[] = {{\n{}}};\n",
+                width.get_bits(),
+                name,
+                cell_list(&cells[start..end], width)
+            );
+        }
+    }
+
+    header += "\n";
+    for (name, address) in exported_labels(program)? {
+        header += &format!("#define SISA_LABEL_{} 0x{:04x}\n", name, address);
+    }
+
+    header += "\n#endif /* SISA_PROGRAM_H */\n";
+    Ok(header.into_bytes())
+}
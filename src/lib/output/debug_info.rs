@@ -0,0 +1,93 @@
+use crate::assembler::{AssembledProgram, FragmentKind};
+use crate::span::SourceMap;
+use crate::symbol_table::{is_expansion_label, SectionId};
+use crate::Span;
+
+pub fn generate(program: &AssembledProgram, source_map: &SourceMap) -> Vec<u8> {
+    let symbol_table = &program.symbol_table;
+    let file = json_file(source_map, 0);
+
+    let sections: Vec<String> = [SectionId::Text, SectionId::Data]
+        .into_iter()
+        .map(|id| {
+            let section = symbol_table.get_section(id);
+            format!(
+                "    {{\"name\": {}, \"start\": {}, \"end\": {}}}",
+                json_string(id.get_name()),
+                section.get_base_address(),
+                section.get_end_address()
+            )
+        })
+        .collect();
+
+    let mut labels = Vec::new();
+    let mut ranges = Vec::new();
+    for fragment in &program.fragments {
+        let span = json_span(source_map, &fragment.span);
+        match &fragment.kind {
+            FragmentKind::Label(name) if is_expansion_label(name) => {}
+            FragmentKind::Label(name) => labels.push(format!(
+                "    {{\"name\": {}, \"address\": {}, \"section\": {}, \"span\": {}}}",
+                json_string(name),
+                fragment.address,
+                symbol_table
+                    .get_symbol(name)
+                    .ok()
+                    .and_then(|s| s.get_section())
+                    .map_or(String::from("null"), |s| json_string(s.get_name())),
+                span
+            )),
+            kind => ranges.push(format!(
+                "    {{\"start\": {}, \"end\": {}, \"kind\": \"{}\", \"span\": {}}}",
+                fragment.address,
+                fragment.address as u32 + fragment.length,
+                if *kind == FragmentKind::Instruction {
+                    "instruction"
+                } else {
+                    "data"
+                },
+                span
+            )),
+        }
+    }
+
+    format!(
+        "{{\n  \"file\": {},\n  \"sections\": [\n{}\n  ],\n  \"labels\": [\n{}\n  ],\n  \"ranges\": [\n{}\n  ]\n}}\n",
+        file,
+        sections.join(",\n"),
+        labels.join(",\n"),
+        ranges.join(",\n")
+    )
+    .into_bytes()
+}
+
+fn json_file(source_map: &SourceMap, context: usize) -> String {
+    let (path, _) = source_map.get_file(context);
+    json_string(&path.to_string_lossy())
+}
+
+fn json_span(source_map: &SourceMap, span: &Span) -> String {
+    format!(
+        "{{\"file\": {}, \"line\": {}, \"column\": {}, \"end_line\": {}, \"end_column\": {}}}",
+        json_file(source_map, span.context),
+        span.lo.line,
+        span.lo.col,
+        span.hi.line,
+        span.hi.col
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            '\n' => json += "\\n",
+            '\t' => json += "\\t",
+            c if (c as u32) < 0x20 => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json + "\""
+}
@@ -0,0 +1,44 @@
+use crate::assembler::AssembledProgram;
+use crate::output::{cell_bytes, memory_cells, section_cells};
+use crate::{Flags, MemoryWidth};
+
+const RECORD_LENGTH: usize = 16;
+
+const DATA_RECORD: u8 = 0x00;
+const EOF_RECORD: u8 = 0x01;
+
+pub fn generate(program: &AssembledProgram, width: MemoryWidth, flags: &Flags) -> Vec<u8> {
+    let cells = memory_cells(&program.machine_code, width, flags.byte_order);
+    let cells_per_record = RECORD_LENGTH / width.get_bytes();
+    let mut hex = String::new();
+
+    for range in section_cells(program, width) {
+        let mut address = range.start;
+        for chunk in cells[range].chunks(cells_per_record) {
+            hex += &record(DATA_RECORD, address as u16, &cell_bytes(chunk, width));
+            address += chunk.len();
+        }
+    }
+    hex += &record(EOF_RECORD, 0, &[]);
+
+    hex.into_bytes()
+}
+
+fn record(record_type: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend(data);
+
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, b| sum.wrapping_add(*b))
+        .wrapping_neg();
+    bytes.push(checksum);
+
+    let mut line = String::from(":");
+    for b in bytes {
+        line += &format!("{:02X}", b);
+    }
+    line + "\n"
+}
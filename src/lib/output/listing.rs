@@ -0,0 +1,140 @@
+use crate::assembler::{AssembledProgram, Fragment, FragmentKind};
+use crate::span::SourceMap;
+use crate::symbol_table::{SectionId, SymbolTable};
+use crate::Flags;
+
+const BYTES_PER_ROW: usize = 4;
+
+pub fn generate(program: &AssembledProgram, source_map: &SourceMap, flags: &Flags) -> Vec<u8> {
+    let mut listing = format!("{:>5}  {:4}  {:11}  SOURCE\n", "LINE", "ADDR", "CODE");
+    list_file(program, source_map, 0, flags, &mut listing, &mut None);
+
+    listing += "\nSymbols:\n";
+    for (name, entry) in program.symbol_table.get_symbols() {
+        listing += &format!(
+            "  {:24} {:04X}  {}\n",
+            name,
+            entry.get_value(),
+            if entry.is_address() {
+                "address"
+            } else {
+                "constant"
+            }
+        );
+    }
+
+    listing.into_bytes()
+}
+
+/// Lists the lines of a file context, followed by the lines of the files they include
+fn list_file(
+    program: &AssembledProgram,
+    source_map: &SourceMap,
+    context: usize,
+    flags: &Flags,
+    listing: &mut String,
+    current_section: &mut Option<SectionId>,
+) {
+    let symbol_table = &program.symbol_table;
+    let (path, code) = source_map.get_file(context);
+
+    for (i, source) in code.lines().enumerate() {
+        let line = i + 1;
+        let fragments: Vec<&Fragment> = program
+            .fragments
+            .iter()
+            .filter(|f| f.span.context == context && f.span.lo.line == line)
+            .collect();
+
+        let section = fragments
+            .iter()
+            .find(|f| !matches!(f.kind, FragmentKind::Label(_)))
+            .and_then(|f| symbol_table.get_section_at(f.address));
+        if let Some(id) = section.filter(|s| Some(*s) != *current_section) {
+            *current_section = section;
+            *listing += &section_header(symbol_table, id);
+        }
+
+        let rows = code_rows(program, &fragments, flags);
+        match rows.split_first() {
+            Some(((address, code), rest)) => {
+                *listing += &format!("{:>5}  {:04X}  {:11}  {}\n", line, address, code, source);
+                for (address, code) in rest {
+                    *listing += &format!("{:>5}  {:04X}  {}\n", "", address, code);
+                }
+            }
+            None => match fragments.first() {
+                Some(label) => {
+                    *listing += &format!(
+                        "{:>5}  {:04X}  {:11}  {}\n",
+                        line, label.address, "", source
+                    )
+                }
+                None => *listing += &format!("{:>5}  {:4}  {:11}  {}\n", line, "", "", source),
+            },
+        }
+
+        for included in source_map.get_included_files(context, line) {
+            let (included_path, _) = source_map.get_file(included);
+            *listing += &file_header("file", &included_path.to_string_lossy());
+            list_file(
+                program,
+                source_map,
+                included,
+                flags,
+                listing,
+                current_section,
+            );
+            *listing += &file_header("back to", &path.to_string_lossy());
+        }
+    }
+}
+
+/// Formats the machine code of the fragments as rows of (address, code). Instructions are shown
+/// as words, and data as bytes.
+fn code_rows(
+    program: &AssembledProgram,
+    fragments: &[&Fragment],
+    flags: &Flags,
+) -> Vec<(u16, String)> {
+    let mut rows = Vec::new();
+
+    for fragment in fragments {
+        let start = fragment.address as usize;
+        let bytes = &program.machine_code[start..start + fragment.length as usize];
+
+        match fragment.kind {
+            FragmentKind::Instruction => rows.push((
+                fragment.address,
+                format!("{:04X}", flags.byte_order.from_bytes([bytes[0], bytes[1]])),
+            )),
+            FragmentKind::Data => {
+                for (i, row) in bytes.chunks(BYTES_PER_ROW).enumerate() {
+                    let row: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+                    rows.push((fragment.address + (i * BYTES_PER_ROW) as u16, row.join(" ")));
+                }
+            }
+            FragmentKind::Label(_) => {}
+        }
+    }
+
+    rows
+}
+
+fn file_header(note: &str, path: &str) -> String {
+    format!("\n{:>5}  {:4}  {:11}  ; {} {}\n", "", "", "", note, path)
+}
+
+fn section_header(symbol_table: &SymbolTable, id: SectionId) -> String {
+    let section = symbol_table.get_section(id);
+
+    format!(
+        "\n{:>5}  {:4}  {:11}  ; section {} ({:04X} - {:04X})\n",
+        "",
+        "",
+        "",
+        id.get_name(),
+        section.get_base_address(),
+        section.get_end_address()
+    )
+}
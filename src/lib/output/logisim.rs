@@ -0,0 +1,36 @@
+use crate::assembler::AssembledProgram;
+use crate::output::memory_cells;
+use crate::{Flags, MemoryWidth};
+
+const ENTRIES_PER_LINE: usize = 8;
+const MIN_RUN_LENGTH: usize = 4;
+
+pub fn generate(program: &AssembledProgram, width: MemoryWidth, flags: &Flags) -> Vec<u8> {
+    let mut cells = memory_cells(&program.machine_code, width, flags.byte_order);
+    // Logisim fills the rest of the memory with zeros
+    while cells.last() == Some(&0) {
+        cells.pop();
+    }
+
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < cells.len() {
+        let run = cells[i..].iter().take_while(|c| **c == cells[i]).count();
+
+        if run >= MIN_RUN_LENGTH {
+            entries.push(format!("{}*{:x}", run, cells[i]));
+            i += run;
+        } else {
+            entries.push(format!("{:x}", cells[i]));
+            i += 1;
+        }
+    }
+
+    let mut image = String::from("v2.0 raw\n");
+    for line in entries.chunks(ENTRIES_PER_LINE) {
+        image += &line.join(" ");
+        image += "\n";
+    }
+
+    image.into_bytes()
+}
@@ -0,0 +1,44 @@
+use crate::assembler::AssembledProgram;
+use crate::symbol_table::SectionId;
+
+pub fn generate(program: &AssembledProgram) -> Vec<u8> {
+    let symbol_table = &program.symbol_table;
+
+    let mut symbols = symbol_table.get_symbols();
+    symbols.sort_by_key(|(_, entry)| entry.get_value());
+
+    let mut map = format!(
+        "# {:22} {:6} {:8} {}\n",
+        "symbol", "value", "kind", "section"
+    );
+    for (name, entry) in symbols {
+        map += &format!(
+            "{:24} 0x{:04x} {:8} {}\n",
+            name,
+            entry.get_value(),
+            if entry.is_address() {
+                "address"
+            } else {
+                "constant"
+            },
+            entry.get_section().map_or("-", |s| s.get_name())
+        );
+    }
+
+    map += &format!(
+        "\n# {:22} {:6} {:6} {}\n",
+        "section", "base", "length", "end"
+    );
+    for id in [SectionId::Text, SectionId::Data] {
+        let section = symbol_table.get_section(id);
+        map += &format!(
+            "{:24} 0x{:04x} 0x{:04x} 0x{:04x}\n",
+            id.get_name(),
+            section.get_base_address(),
+            section.get_length(),
+            section.get_end_address()
+        );
+    }
+
+    map.into_bytes()
+}
@@ -0,0 +1,86 @@
+use crate::assembler::{AssembledProgram, FragmentKind};
+use crate::output::{memory_cells, memory_depth, source_line};
+use crate::span::SourceMap;
+use crate::{Flags, MemoryWidth};
+use std::collections::{HashMap, HashSet};
+
+pub fn generate(
+    program: &AssembledProgram,
+    source_map: &SourceMap,
+    width: MemoryWidth,
+    flags: &Flags,
+) -> Result<Vec<u8>, String> {
+    let cells = memory_cells(&program.machine_code, width, flags.byte_order);
+    let depth = memory_depth(cells.len(), flags)?;
+    let digits = (width.get_bits() / 4) as usize;
+    let address_digits = format!("{:X}", depth - 1).len().max(4);
+
+    let mut comments = HashMap::new();
+    let mut instruction_cells = HashSet::new();
+    for fragment in &program.fragments {
+        if fragment.kind == FragmentKind::Instruction {
+            let first_cell = fragment.address as usize / width.get_bytes();
+            let last_cell =
+                (fragment.address as u32 + fragment.length - 1) as usize / width.get_bytes();
+            comments.entry(first_cell).or_insert_with(|| {
+                let (_, code) = source_map.get_file(fragment.span.context);
+                source_line(code, fragment.span.lo.line)
+            });
+            instruction_cells.extend(first_cell..=last_cell);
+        }
+    }
+
+    let mut mif = format!(
+        "WIDTH={};\nDEPTH={};\n\nADDRESS_RADIX=HEX;\nDATA_RADIX=HEX;\n\nCONTENT BEGIN\n",
+        width.get_bits(),
+        depth
+    );
+
+    let mut address = 0;
+    while address < depth {
+        let value = cells.get(address).copied().unwrap_or(0);
+
+        if let Some(comment) = comments.get(&address) {
+            mif += &format!(
+                "\t{:0aw$X} : {:0dw$X};\t-- {}\n",
+                address,
+                value,
+                comment,
+                aw = address_digits,
+                dw = digits
+            );
+            address += 1;
+        } else if value == 0 && !instruction_cells.contains(&address) {
+            let start = address;
+            while address < depth
+                && cells.get(address).copied().unwrap_or(0) == 0
+                && !instruction_cells.contains(&address)
+            {
+                address += 1;
+            }
+
+            if address - start == 1 {
+                mif += &format!("\t{:0aw$X} : 0;\n", start, aw = address_digits);
+            } else {
+                mif += &format!(
+                    "\t[{:0aw$X}..{:0aw$X}] : 0;\n",
+                    start,
+                    address - 1,
+                    aw = address_digits
+                );
+            }
+        } else {
+            mif += &format!(
+                "\t{:0aw$X} : {:0dw$X};\n",
+                address,
+                value,
+                aw = address_digits,
+                dw = digits
+            );
+            address += 1;
+        }
+    }
+
+    mif += "END;\n";
+    Ok(mif.into_bytes())
+}
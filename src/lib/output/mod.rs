@@ -0,0 +1,160 @@
+use crate::assembler::AssembledProgram;
+use crate::span::SourceMap;
+use crate::{ByteOrder, Flags, MemoryWidth, Output, OutputFormat};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path;
+
+mod binary;
+mod c;
+mod debug_info;
+mod ihex;
+mod listing;
+mod logisim;
+mod map;
+mod mif;
+mod object;
+mod readmemh;
+mod rust;
+mod srec;
+mod vhdl;
+mod words;
+
+pub struct OutputFile {
+    pub path: path::PathBuf,
+    pub contents: Vec<u8>,
+}
+
+pub fn generate(
+    program: &AssembledProgram,
+    source_map: &SourceMap,
+    output: &Output,
+    flags: &Flags,
+) -> Result<Vec<OutputFile>, String> {
+    let width = flags
+        .memory_width
+        .unwrap_or(output.format.get_default_memory_width());
+
+    let contents = match output.format {
+        OutputFormat::Binary => binary::generate(program, width, flags),
+        OutputFormat::Sections if crate::is_std_stream(&output.path) => {
+            return Err(String::from(
+                "The sections format can't be written to stdout",
+            ))
+        }
+        OutputFormat::Sections => return Ok(binary::generate_sections(program, &output.path)),
+        OutputFormat::IntelHex => ihex::generate(program, width, flags),
+        OutputFormat::SRecord => srec::generate(program, width, flags),
+        OutputFormat::Mif => mif::generate(program, source_map, width, flags)?,
+        OutputFormat::Readmemh => readmemh::generate(program, width, flags),
+        OutputFormat::Vhdl => vhdl::generate(program, width, flags)?,
+        OutputFormat::Logisim => logisim::generate(program, width, flags),
+        OutputFormat::C => c::generate(program, width, flags)?,
+        OutputFormat::Rust => rust::generate(program, width, flags)?,
+        OutputFormat::Listing => listing::generate(program, source_map, flags),
+        OutputFormat::Map => map::generate(program),
+        OutputFormat::DebugInfo => debug_info::generate(program, source_map),
+        OutputFormat::HexWords => words::generate(program, width, flags, false),
+        OutputFormat::Bits => words::generate(program, width, flags, true),
+        OutputFormat::Object => object::generate(program, flags)?,
+    };
+
+    Ok(vec![OutputFile {
+        path: output.path.clone(),
+        contents,
+    }])
+}
+
+/// Returns the range of memory cells covered by every non-empty section. A cell shared by two
+/// sections is only included in the first one.
+fn section_cells(program: &AssembledProgram, width: MemoryWidth) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+
+    for section in program.symbol_table.get_sections() {
+        let start = section.get_base_address() as usize;
+        let end = start + section.get_length() as usize;
+        let mut range = start / width.get_bytes()..end.div_ceil(width.get_bytes());
+
+        if let Some(previous) = ranges.last() {
+            range.start = range.start.max(previous.end);
+        }
+        if !range.is_empty() {
+            ranges.push(range);
+        }
+    }
+
+    ranges
+}
+
+/// Splits the memory image in cells of the given width
+fn memory_cells(image: &[u8], width: MemoryWidth, byte_order: ByteOrder) -> Vec<u16> {
+    match width {
+        MemoryWidth::Byte => image.iter().map(|b| *b as u16).collect(),
+        MemoryWidth::Word => image
+            .chunks(2)
+            .map(|w| byte_order.from_bytes([w[0], *w.get(1).unwrap_or(&0)]))
+            .collect(),
+    }
+}
+
+/// Serializes memory cells most significant byte first, as hex formats expect
+fn cell_bytes(cells: &[u16], width: MemoryWidth) -> Vec<u8> {
+    match width {
+        MemoryWidth::Byte => cells.iter().map(|c| *c as u8).collect(),
+        MemoryWidth::Word => cells.iter().flat_map(|c| c.to_be_bytes()).collect(),
+    }
+}
+
+/// Returns the memory depth requested by the user, or the minimum depth that fits the program
+fn memory_depth(cells: usize, flags: &Flags) -> Result<usize, String> {
+    match flags.memory_depth {
+        Some(depth) if (depth as usize) < cells => Err(format!(
+            "The program needs {} memory cells, but the memory depth is {}",
+            cells, depth
+        )),
+        Some(depth) => Ok(depth as usize),
+        None => Ok(cells.max(1)),
+    }
+}
+
+/// Returns the label addresses, with names usable as C and Rust constants. Fails if two labels
+/// only differ in case, as they would be exported with the same name.
+fn exported_labels(program: &AssembledProgram) -> Result<Vec<(String, u16)>, String> {
+    let mut labels: Vec<(String, u16)> = Vec::new();
+    let mut exported_names = HashMap::new();
+
+    for (name, entry) in program.symbol_table.get_symbols() {
+        if !entry.is_address() {
+            continue;
+        }
+
+        let exported_name = name.to_uppercase();
+        if let Some(other) = exported_names.insert(exported_name.clone(), name) {
+            return Err(format!(
+                "The labels {} and {} would both be exported as {}",
+                other, name, exported_name
+            ));
+        }
+        labels.push((exported_name, entry.get_value()));
+    }
+
+    Ok(labels)
+}
+
+/// Formats the cells as a comma separated list of hex values, for C and Rust arrays
+fn cell_list(cells: &[u16], width: MemoryWidth) -> String {
+    let digits = (width.get_bits() / 4) as usize;
+    let mut list = String::new();
+    for line in cells.chunks(24 / digits) {
+        let line: Vec<String> = line
+            .iter()
+            .map(|c| format!("0x{:0w$x}", c, w = digits))
+            .collect();
+        list += &format!("    {},\n", line.join(", "));
+    }
+    list
+}
+
+fn source_line(code: &str, line: usize) -> &str {
+    code.lines().nth(line - 1).unwrap_or_default().trim()
+}
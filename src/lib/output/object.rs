@@ -0,0 +1,174 @@
+//! Relocatable object files
+//!
+//! Every multi-byte field of the object is little endian, while the section contents keep the
+//! byte order selected with `--byte-order`. An object is laid out as:
+//!
+//! ```text
+//! magic       "SOBJ"
+//! version     u8, currently 1
+//! flags       u8, bit 0 is set when the sections store big endian words
+//! text length u16
+//! data length u16
+//! symbols     u16, number of symbol entries
+//! relocations u16, number of relocation entries
+//! .text contents
+//! .data contents
+//! symbol entries:
+//!     binding u8 (0 local, 1 global)
+//!     section u8 (0 undefined, 1 .text, 2 .data, 3 absolute)
+//!     value   u16, relative to the start of the section
+//!     name    u8 length followed by the name bytes
+//! relocation entries:
+//!     section u8 (1 .text, 2 .data)
+//!     offset  u16, relative to the start of the section
+//!     field   u8 (0 byte, 1 word, 2 6 bit immediate, 3 8 bit immediate)
+//!     kind    u8 (0 absolute, 1 lo(), 2 hi(), 3 relative to the next instruction, in words)
+//!     symbol  u16, index of the symbol entry
+//!     addend  u16
+//! ```
+//!
+//! The relocated fields hold the value computed for the current layout, so the linker must
+//! overwrite them with the one obtained from the final symbol address plus the addend.
+
+use crate::assembler::{AssembledProgram, RelocationField, RelocationKind};
+use crate::symbol_table::SectionId;
+use crate::{ByteOrder, Flags};
+use std::collections::BTreeSet;
+
+const MAGIC: &[u8; 4] = b"SOBJ";
+const VERSION: u8 = 1;
+
+const SECTION_UNDEFINED: u8 = 0;
+const SECTION_ABSOLUTE: u8 = 3;
+
+pub fn generate(program: &AssembledProgram, flags: &Flags) -> Result<Vec<u8>, String> {
+    let symbol_table = &program.symbol_table;
+    let text = section_contents(program, SectionId::Text);
+    let data = section_contents(program, SectionId::Data);
+    for (id, contents) in [(SectionId::Text, text), (SectionId::Data, data)] {
+        if contents.len() > u16::MAX as usize {
+            return Err(format!(
+                "Objects can't store the {} bytes of {}, which fill the whole memory",
+                contents.len(),
+                id.get_name()
+            ));
+        }
+    }
+
+    // Symbols referenced or exported without a definition are left for the linker
+    let externals: BTreeSet<&str> = program
+        .relocations
+        .iter()
+        .map(|r| r.symbol.as_str())
+        .chain(symbol_table.get_globals())
+        .filter(|name| symbol_table.get_symbol(name).is_err())
+        .collect();
+
+    let mut symbols = Vec::new();
+    let mut names = Vec::new();
+    // Relocations may refer to the labels of macro expansions, so they are kept
+    for (name, entry) in symbol_table.get_all_symbols() {
+        let (section, value) = match entry.get_section() {
+            Some(id) => (
+                section_number(id),
+                entry.get_value() - symbol_table.get_section(id).get_base_address(),
+            ),
+            None => (SECTION_ABSOLUTE, entry.get_value()),
+        };
+        push_symbol(
+            &mut symbols,
+            name,
+            symbol_table.is_global(name),
+            section,
+            value,
+        )?;
+        names.push(name);
+    }
+    for name in externals {
+        push_symbol(&mut symbols, name, true, SECTION_UNDEFINED, 0)?;
+        names.push(name);
+    }
+
+    let mut relocations = Vec::new();
+    for relocation in &program.relocations {
+        let id = symbol_table
+            .get_section_at(relocation.address)
+            .ok_or(format!(
+                "Can't relocate address {:#06X}, it's outside every section",
+                relocation.address
+            ))?;
+        let offset = relocation.address - symbol_table.get_section(id).get_base_address();
+        let symbol = names.iter().position(|n| *n == relocation.symbol).unwrap() as u16;
+
+        relocations.push(section_number(id));
+        relocations.extend(offset.to_le_bytes());
+        relocations.push(field_number(relocation.field));
+        relocations.push(kind_number(relocation.kind));
+        relocations.extend(symbol.to_le_bytes());
+        relocations.extend(relocation.addend.to_le_bytes());
+    }
+
+    let mut object = Vec::new();
+    object.extend(MAGIC);
+    object.push(VERSION);
+    object.push((flags.byte_order == ByteOrder::Big) as u8);
+    object.extend((text.len() as u16).to_le_bytes());
+    object.extend((data.len() as u16).to_le_bytes());
+    object.extend((names.len() as u16).to_le_bytes());
+    object.extend((program.relocations.len() as u16).to_le_bytes());
+    object.extend(text);
+    object.extend(data);
+    object.extend(symbols);
+    object.extend(relocations);
+
+    Ok(object)
+}
+
+fn section_contents(program: &AssembledProgram, id: SectionId) -> &[u8] {
+    let section = program.symbol_table.get_section(id);
+    &program.machine_code[section.get_base_address() as usize..section.get_end_address() as usize]
+}
+
+fn push_symbol(
+    symbols: &mut Vec<u8>,
+    name: &str,
+    global: bool,
+    section: u8,
+    value: u16,
+) -> Result<(), String> {
+    if name.len() > u8::MAX as usize {
+        return Err(format!("Symbol {} is too long for an object file", name));
+    }
+
+    symbols.push(global as u8);
+    symbols.push(section);
+    symbols.extend(value.to_le_bytes());
+    symbols.push(name.len() as u8);
+    symbols.extend(name.as_bytes());
+    Ok(())
+}
+
+fn section_number(id: SectionId) -> u8 {
+    match id {
+        SectionId::Text => 1,
+        SectionId::Data => 2,
+    }
+}
+
+fn field_number(field: RelocationField) -> u8 {
+    match field {
+        RelocationField::Byte => 0,
+        RelocationField::Word => 1,
+        RelocationField::Imm6 => 2,
+        RelocationField::Imm8 => 3,
+    }
+}
+
+fn kind_number(kind: RelocationKind) -> u8 {
+    match kind {
+        RelocationKind::Absolute => 0,
+        RelocationKind::Lo => 1,
+        RelocationKind::Hi => 2,
+        RelocationKind::PcRelative => 3,
+    }
+}
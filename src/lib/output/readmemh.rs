@@ -0,0 +1,25 @@
+use crate::assembler::AssembledProgram;
+use crate::output::{memory_cells, section_cells};
+use crate::{Flags, MemoryWidth};
+
+const CELLS_PER_LINE: usize = 8;
+
+pub fn generate(program: &AssembledProgram, width: MemoryWidth, flags: &Flags) -> Vec<u8> {
+    let cells = memory_cells(&program.machine_code, width, flags.byte_order);
+    let digits = (width.get_bits() / 4) as usize;
+
+    let mut memh = String::new();
+    for range in section_cells(program, width) {
+        memh += &format!("@{:04X}\n", range.start);
+        for line in cells[range].chunks(CELLS_PER_LINE) {
+            let line: Vec<String> = line
+                .iter()
+                .map(|c| format!("{:0w$X}", c, w = digits))
+                .collect();
+            memh += &line.join(" ");
+            memh += "\n";
+        }
+    }
+
+    memh.into_bytes()
+}
@@ -0,0 +1,45 @@
+use crate::assembler::AssembledProgram;
+use crate::output::{cell_list, exported_labels, memory_cells};
+use crate::symbol_table::SectionId;
+use crate::{Flags, MemoryWidth};
+
+pub fn generate(
+    program: &AssembledProgram,
+    width: MemoryWidth,
+    flags: &Flags,
+) -> Result<Vec<u8>, String> {
+    let symbol_table = &program.symbol_table;
+    let cells = memory_cells(&program.machine_code, width, flags.byte_order);
+    let mut source = String::new();
+
+    for id in [SectionId::Text, SectionId::Data] {
+        let name = id.get_name().trim_start_matches('.').to_uppercase();
+        let section = symbol_table.get_section(id);
+        let start = section.get_base_address() as usize / width.get_bytes();
+        let end = (section.get_base_address() as usize + section.get_length() as usize)
+            .div_ceil(width.get_bytes());
+
+        source += &format!(
+            "pub const {}_START: u16 = 0x{:04x};\npub const {}_LENGTH: usize = 0x{:04x};\n",
+            name,
+            section.get_base_address(),
+            name,
+            section.get_length()
+        );
+        source += &format!(
+            "pub const {}: [u{}; {}] = [\n{}];\n\n",
+            name,
+            width.get_bits(),
+            end - start,
+            cell_list(&cells[start..end], width)
+        );
+    }
+
+    source += "pub mod labels {\n";
+    for (name, address) in exported_labels(program)? {
+        source += &format!("    pub const {}: u16 = 0x{:04x};\n", name, address);
+    }
+    source += "}\n";
+
+    Ok(source.into_bytes())
+}
@@ -0,0 +1,38 @@
+use crate::assembler::AssembledProgram;
+use crate::output::{cell_bytes, memory_cells, section_cells};
+use crate::{Flags, MemoryWidth};
+
+const RECORD_LENGTH: usize = 16;
+
+pub fn generate(program: &AssembledProgram, width: MemoryWidth, flags: &Flags) -> Vec<u8> {
+    let cells = memory_cells(&program.machine_code, width, flags.byte_order);
+    let cells_per_record = RECORD_LENGTH / width.get_bytes();
+    let mut srec = record('0', 0, &[]);
+
+    for range in section_cells(program, width) {
+        let mut address = range.start;
+        for chunk in cells[range].chunks(cells_per_record) {
+            srec += &record('1', address as u16, &cell_bytes(chunk, width));
+            address += chunk.len();
+        }
+    }
+    let entry = program.symbol_table.get_text_section_base_address() as usize / width.get_bytes();
+    srec += &record('9', entry as u16, &[]);
+
+    srec.into_bytes()
+}
+
+fn record(record_type: char, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8 + 3];
+    bytes.extend(address.to_be_bytes());
+    bytes.extend(data);
+
+    let checksum = !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(checksum);
+
+    let mut line = format!("S{}", record_type);
+    for b in bytes {
+        line += &format!("{:02X}", b);
+    }
+    line + "\n"
+}
@@ -0,0 +1,40 @@
+use crate::assembler::AssembledProgram;
+use crate::output::{memory_cells, memory_depth, section_cells};
+use crate::{Flags, MemoryWidth};
+
+const PACKAGE_NAME: &str = "sisa_rom";
+
+pub fn generate(
+    program: &AssembledProgram,
+    width: MemoryWidth,
+    flags: &Flags,
+) -> Result<Vec<u8>, String> {
+    let cells = memory_cells(&program.machine_code, width, flags.byte_order);
+    let depth = memory_depth(cells.len(), flags)?;
+    let digits = (width.get_bits() / 4) as usize;
+
+    let mut vhdl = String::from("library ieee;\nuse ieee.std_logic_1164.all;\n\n");
+    vhdl += &format!("package {} is\n", PACKAGE_NAME);
+    vhdl += &format!(
+        "    type rom_t is array (0 to {}) of std_logic_vector({} downto 0);\n\n",
+        depth - 1,
+        width.get_bits() - 1
+    );
+    vhdl += "    constant ROM : rom_t := (\n";
+
+    for range in section_cells(program, width) {
+        for address in range {
+            vhdl += &format!(
+                "        {} => x\"{:0w$X}\",\n",
+                address,
+                cells[address],
+                w = digits
+            );
+        }
+    }
+
+    vhdl += &format!("        others => x\"{:0w$X}\"\n    );\n", 0, w = digits);
+    vhdl += &format!("end package {};\n", PACKAGE_NAME);
+
+    Ok(vhdl.into_bytes())
+}
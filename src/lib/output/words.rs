@@ -0,0 +1,47 @@
+use crate::assembler::AssembledProgram;
+use crate::output::{memory_cells, section_cells};
+use crate::{Flags, MemoryWidth};
+use std::collections::HashMap;
+
+pub fn generate(
+    program: &AssembledProgram,
+    width: MemoryWidth,
+    flags: &Flags,
+    binary: bool,
+) -> Vec<u8> {
+    let symbol_table = &program.symbol_table;
+    let cells = memory_cells(&program.machine_code, width, flags.byte_order);
+    let bits = width.get_bits() as usize;
+
+    let mut labels: HashMap<usize, Vec<&str>> = HashMap::new();
+    for (name, entry) in symbol_table.get_symbols() {
+        if entry.is_address() {
+            let label_cell = entry.get_value() as usize / width.get_bytes();
+            labels.entry(label_cell).or_default().push(name);
+        }
+    }
+
+    let mut dump = String::new();
+    for range in section_cells(program, width) {
+        for cell in range {
+            let address = (cell * width.get_bytes()) as u16;
+            if binary {
+                dump += &format!("{:04X}  {:0w$b}", address, cells[cell], w = bits);
+            } else {
+                dump += &format!("{:04X}  {:0w$X}", address, cells[cell], w = bits / 4);
+            }
+
+            if flags.annotate {
+                let section = symbol_table.get_section_at(address);
+                dump += &format!("  {}", section.map_or("", |s| s.get_name()));
+
+                for name in labels.get(&cell).into_iter().flatten() {
+                    dump += &format!(" {}:", name);
+                }
+            }
+            dump += "\n";
+        }
+    }
+
+    dump.into_bytes()
+}
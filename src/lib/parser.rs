@@ -9,6 +9,12 @@ pub struct Parser<T: Iterator<Item = (Token, Span)>> {
     tokens: T,
 }
 
+/// What the parser reads: a whole program, or the lone expression of a preprocessor directive
+enum Parsed {
+    Program(Node<Span, Program>),
+    Expression(Node<Span, Literal>),
+}
+
 #[allow(unused_braces)] // parser! {} generates a lot of those warnings
 #[allow(clippy::redundant_closure_call)] // same deal
 #[allow(clippy::ptr_arg)] // some more
@@ -17,12 +23,23 @@ impl<T: Iterator<Item = (Token, Span)>> Parser<T> {
         fn _parse(Token, Span);
 
         (a, b) {
-            Span {
-                lo: a.lo,
-                hi: b.hi
+            // Code expanded from a macro mixes tokens of the body and of the arguments
+            if a.context == b.context {
+                Span {
+                    lo: a.lo,
+                    hi: b.hi,
+                    context: a.context
+                }
+            } else {
+                a
             }
         }
 
+        parsed: Parsed {
+            program[p] => Parsed::Program(p),
+            BeginExpression lit[e] End => Parsed::Expression(e),
+        }
+
         program: Node<Span, Program> {
             data_section[ds] End => {
                 let mut p = Program::empty();
@@ -47,7 +64,11 @@ impl<T: Iterator<Item = (Token, Span)>> Parser<T> {
                 p.to_node(span!())
             }
             constant[c] program[mut p] => {
-                p.get_data_mut().constants.push(c);
+                p.get_data_mut().constants.insert(0, c);
+                p
+            }
+            global[g] program[mut p] => {
+                p.get_data_mut().globals.insert(0, g);
                 p
             }
         }
@@ -75,6 +96,7 @@ impl<T: Iterator<Item = (Token, Span)>> Parser<T> {
             label[l] => Statement::Label(l).to_node(span!()),
             raw_data[r] => Statement::RawData(r).to_node(span!()),
             constant[c] => Statement::Constant(c).to_node(span!()),
+            global[g] => Statement::Global(g).to_node(span!()),
         }
 
         instruction: Node<Span, Instruction> {
@@ -116,9 +138,20 @@ impl<T: Iterator<Item = (Token, Span)>> Parser<T> {
         raw_data: Node<Span, RawData> {
             bytes[b] => RawData::Bytes(b).to_node(span!()),
             words[w] => RawData::Words(w).to_node(span!()),
-            Space Lit(bytes) => RawData::Bytes(vec![Literal::Constant(0).to_node(span!());bytes as usize]).to_node(span!()),
-            Space Lit(bytes) Comma lit[data] => RawData::Bytes(vec![data;bytes as usize]).to_node(span!()),
+            Space lit[size] => RawData::Space { size, fill: Literal::Constant(0).to_node(span!()) }.to_node(span!()),
+            Space lit[size] Comma lit[fill] => RawData::Space { size, fill }.to_node(span!()),
             Even => RawData::WordAlign.to_node(span!()),
+            Incbin Binary(data) => RawData::Binary(data).to_node(span!()),
+            Ascii strings[s] => RawData::from_strings(s, false, span!()).to_node(span!()),
+            Asciz strings[s] => RawData::from_strings(s, true, span!()).to_node(span!()),
+        }
+
+        strings: Vec<Vec<u8>> {
+            Str(s) => vec![s],
+            strings[mut strings] Comma Str(s) => {
+                strings.push(s);
+                strings
+            }
         }
 
         bytes: Vec<Node<Span, Literal>> {
@@ -142,9 +175,65 @@ impl<T: Iterator<Item = (Token, Span)>> Parser<T> {
         }
 
         lit: Node<Span, Literal> {
+            lit[lhs] Pipe xor_expr[rhs] => Literal::binary(BinaryOperator::Or, lhs, rhs, span!()),
+            xor_expr[e] => e,
+        }
+
+        xor_expr: Node<Span, Literal> {
+            xor_expr[lhs] Caret and_expr[rhs] => Literal::binary(BinaryOperator::Xor, lhs, rhs, span!()),
+            and_expr[e] => e,
+        }
+
+        and_expr: Node<Span, Literal> {
+            and_expr[lhs] Ampersand equality_expr[rhs] => Literal::binary(BinaryOperator::And, lhs, rhs, span!()),
+            equality_expr[e] => e,
+        }
+
+        equality_expr: Node<Span, Literal> {
+            equality_expr[lhs] DoubleEquals relational_expr[rhs] => Literal::binary(BinaryOperator::Equal, lhs, rhs, span!()),
+            equality_expr[lhs] NotEquals relational_expr[rhs] => Literal::binary(BinaryOperator::NotEqual, lhs, rhs, span!()),
+            relational_expr[e] => e,
+        }
+
+        relational_expr: Node<Span, Literal> {
+            relational_expr[lhs] Less shift_expr[rhs] => Literal::binary(BinaryOperator::Less, lhs, rhs, span!()),
+            relational_expr[lhs] LessEquals shift_expr[rhs] => Literal::binary(BinaryOperator::LessEqual, lhs, rhs, span!()),
+            relational_expr[lhs] Greater shift_expr[rhs] => Literal::binary(BinaryOperator::Greater, lhs, rhs, span!()),
+            relational_expr[lhs] GreaterEquals shift_expr[rhs] => Literal::binary(BinaryOperator::GreaterEqual, lhs, rhs, span!()),
+            shift_expr[e] => e,
+        }
+
+        shift_expr: Node<Span, Literal> {
+            shift_expr[lhs] ShiftLeft sum_expr[rhs] => Literal::binary(BinaryOperator::ShiftLeft, lhs, rhs, span!()),
+            shift_expr[lhs] ShiftRight sum_expr[rhs] => Literal::binary(BinaryOperator::ShiftRight, lhs, rhs, span!()),
+            sum_expr[e] => e,
+        }
+
+        sum_expr: Node<Span, Literal> {
+            sum_expr[lhs] Plus product_expr[rhs] => Literal::binary(BinaryOperator::Add, lhs, rhs, span!()),
+            sum_expr[lhs] Minus product_expr[rhs] => Literal::binary(BinaryOperator::Sub, lhs, rhs, span!()),
+            product_expr[e] => e,
+        }
+
+        product_expr: Node<Span, Literal> {
+            product_expr[lhs] Star unary_expr[rhs] => Literal::binary(BinaryOperator::Mul, lhs, rhs, span!()),
+            product_expr[lhs] Slash unary_expr[rhs] => Literal::binary(BinaryOperator::Div, lhs, rhs, span!()),
+            product_expr[lhs] Percent unary_expr[rhs] => Literal::binary(BinaryOperator::Rem, lhs, rhs, span!()),
+            unary_expr[e] => e,
+        }
+
+        unary_expr: Node<Span, Literal> {
+            Minus unary_expr[e] => Literal::unary(UnaryOperator::Negate, e, span!()),
+            Tilde unary_expr[e] => Literal::unary(UnaryOperator::Not, e, span!()),
+            Plus unary_expr[e] => e,
+            atom[a] => a,
+        }
+
+        atom: Node<Span, Literal> {
             Lit(val) => Literal::Constant(val).to_node(span!()),
             symbol_ref[sr] => Literal::SymbolRef(sr).to_node(span!()),
             function[f] => Literal::Function(f).to_node(span!()),
+            Lpar lit[e] Rpar => e,
         }
 
         label: Node<Span, Label> {
@@ -164,6 +253,10 @@ impl<T: Iterator<Item = (Token, Span)>> Parser<T> {
             Set Ident(name) Comma lit[value] => Constant::new(name, value).to_node(span!()),
             Ident(name) Equals lit[value] => Constant::new(name, value).to_node(span!()),
         }
+
+        global: Node<Span, Global> {
+            Globl Ident(name) => Global { name }.to_node(span!()),
+        }
     }
 
     pub fn new(tokens: T) -> Self {
@@ -171,8 +264,11 @@ impl<T: Iterator<Item = (Token, Span)>> Parser<T> {
     }
 
     pub fn parse(self) -> Result<Node<Span, Program>, AssemblerMessage> {
-        Self::_parse(self.tokens).map_err(|(element, description)| {
-            if let Some((_, span)) = element {
+        match Self::_parse(self.tokens) {
+            Ok(Parsed::Program(program)) => Ok(program),
+            // The lexer never produces BeginExpression
+            Ok(Parsed::Expression(_)) => unreachable!(),
+            Err((element, description)) => Err(if let Some((_, span)) = element {
                 AssemblerMessage {
                     msg_type: AssemblerMessageType::Error,
                     description: description.into(),
@@ -184,7 +280,27 @@ impl<T: Iterator<Item = (Token, Span)>> Parser<T> {
                     description: description.into(),
                     span: None,
                 }
-            }
-        })
+            }),
+        }
+    }
+
+    /// Parses the tokens as a single expression. Errors at the end of the tokens have no span.
+    pub fn parse_expression(
+        self,
+        end: Span,
+    ) -> Result<Node<Span, Literal>, (String, Option<Span>)> {
+        let tokens = std::iter::once((BeginExpression, end))
+            .chain(self.tokens)
+            .chain(std::iter::once((End, end)));
+        match Self::_parse(tokens) {
+            Ok(Parsed::Expression(expression)) => Ok(expression),
+            Ok(Parsed::Program(_)) => unreachable!(),
+            Err((element, description)) => Err((
+                description.into(),
+                element
+                    .filter(|(token, _)| *token != End)
+                    .map(|(_, span)| span),
+            )),
+        }
     }
 }
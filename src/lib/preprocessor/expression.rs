@@ -0,0 +1,81 @@
+//! Evaluation of the constant expressions used by the preprocessor directives. They are parsed and
+//! folded like the ones of the assembler, but can only use the constants known by the preprocessor.
+
+use crate::nodes::{AddressValue, Value};
+use crate::parser::Parser;
+use crate::span::Span;
+use crate::symbol_table::SectionId;
+use crate::tokens::Token;
+use std::collections::HashMap;
+
+/// The preprocessor doesn't know any address, so its expressions can't produce them
+enum NoAddress {}
+
+/// Evaluates the tokens of an expression following a directive at `span`
+pub fn evaluate(
+    tokens: &[(Token, Span)],
+    span: Span,
+    symbols: &HashMap<String, Option<i32>>,
+) -> Result<i32, (String, Span)> {
+    if tokens.is_empty() {
+        return Err((String::from("Expected an expression"), span));
+    }
+    let end = tokens.last().map_or(span, |(_, span)| *span);
+    let parse =
+        |tokens: &[(Token, Span)]| Parser::new(tokens.iter().cloned()).parse_expression(end);
+
+    let expression = parse(tokens).map_err(|(description, span)| match span {
+        // The parser expects any token of the grammar after a complete expression, so say that
+        // instead of listing them
+        Some(span)
+            if tokens
+                .iter()
+                .position(|(_, s)| *s == span)
+                .is_some_and(|i| i > 0 && parse(&tokens[..i]).is_ok()) =>
+        {
+            (String::from("Unexpected token after the expression"), span)
+        }
+        Some(span) => (description, span),
+        None => (description, end),
+    })?;
+
+    let value = expression.get_data().evaluate::<NoAddress>(
+        &|sr| {
+            let name = &sr.get_data().name;
+            match symbols.get(name) {
+                Some(Some(value)) => Ok(Value::Constant(*value)),
+                Some(None) => Err((
+                    format!(
+                        "The value of {} isn't known at this point, conditions can only use constants",
+                        name
+                    ),
+                    *sr.get_common(),
+                )),
+                None => Err((
+                    format!("Symbol {} isn't defined at this point", name),
+                    *sr.get_common(),
+                )),
+            }
+        },
+        false,
+    )?;
+
+    match value {
+        Value::Constant(value) => Ok(value),
+        Value::Address(address) => match address {},
+    }
+}
+
+impl AddressValue for NoAddress {
+    fn get_value(&self) -> u16 {
+        match *self {}
+    }
+
+    fn get_section(&self) -> Option<SectionId> {
+        match *self {}
+    }
+
+    fn with_offset(self, _offset: i32) -> Self {
+        match self {}
+    }
+}
@@ -0,0 +1,827 @@
+//! Macro expansion and conditional assembly, done on the tokens of the lexer before they reach the
+//! parser.
+//!
+//! Directives are line based: a `.macro NAME param1, param2=default` header, the body lines and an
+//! `.endm` line define a macro, and a line starting with its name (after any labels) invokes it
+//! with the rest of the line as comma separated arguments. The body is expanded with every
+//! parameter replaced by the tokens of its argument, and the labels it defines renamed so that
+//! each expansion gets its own.
+//!
+//! Conditionals (`.if`, `.ifdef`, `.ifndef`, `.elseif`, `.else` and `.endif`) drop the lines of
+//! the branches that aren't taken. Their conditions only see the symbols defined before them.
+//!
+//! Repetitions (`.rept`, `.irp` and `.irpc`, up to `.endr`) expand their body once for every
+//! iteration, replacing a symbol by the counter or the value of the iteration.
+//!
+//! `.include "path"` replaces its line with the lines of another file, looked up next to the file
+//! including it and then in the include paths. `.incbin "path"` is looked up the same way, and its
+//! contents are read here and passed to the parser.
+
+mod expression;
+
+use crate::assembler::message::{AssemblerMessage, AssemblerMessageType};
+use crate::span::{SourceMap, Span, SpanContext};
+use crate::symbol_table::expansion_label;
+use crate::tokens::Token;
+use crate::{Flags, Lexer};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// How many macro invocations can be nested inside each other
+const MAX_MACRO_DEPTH: usize = 16;
+
+/// How many times a `.rept` can repeat its body
+const MAX_REPETITIONS: i32 = 0x10000;
+
+type Line = Vec<(Token, Span)>;
+
+struct MacroParameter {
+    name: String,
+    default: Option<Line>,
+}
+
+struct MacroDefinition {
+    parameters: Vec<MacroParameter>,
+    body: Vec<Line>,
+    /// Labels defined by the body, which are renamed in every expansion
+    labels: HashSet<String>,
+    /// The context the body was written in
+    context: usize,
+}
+
+struct Conditional {
+    span: Span,
+    state: ConditionalState,
+    seen_else: bool,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum ConditionalState {
+    /// The current branch is assembled
+    Active,
+    /// No branch has been taken yet
+    Pending,
+    /// A previous branch was taken, or the whole conditional is skipped
+    Done,
+}
+
+pub struct Preprocessor<'a> {
+    source_map: &'a mut SourceMap,
+    macros: HashMap<String, Rc<MacroDefinition>>,
+    /// The symbols defined so far, with their value if it's a constant the preprocessor knows
+    symbols: HashMap<String, Option<i32>>,
+    /// The files being processed, as (canonical path, path), from the main source file to the
+    /// innermost `.include`
+    include_stack: Vec<(PathBuf, PathBuf)>,
+    messages: Vec<AssemblerMessage>,
+    /// Set by the first `.end` that isn't skipped, after which no more lines are processed
+    ended: bool,
+    flags: &'a Flags,
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(source_map: &'a mut SourceMap, flags: &'a Flags) -> Self {
+        let (path, _) = source_map.get_file(0);
+        let include_stack = vec![(canonical_path(path), path.to_path_buf())];
+
+        Self {
+            source_map,
+            macros: HashMap::new(),
+            symbols: flags
+                .defines
+                .iter()
+                .map(|(name, value)| (name.clone(), Some(*value)))
+                .collect(),
+            include_stack,
+            messages: Vec::new(),
+            ended: false,
+            flags,
+        }
+    }
+
+    pub fn get_messages(&self) -> Vec<AssemblerMessage> {
+        self.messages.clone()
+    }
+
+    /// Expands every macro invocation of the tokens, removing the macro definitions and the
+    /// branches of the conditionals that aren't taken
+    pub fn preprocess<T: IntoIterator<Item = (Token, Span)>>(
+        &mut self,
+        tokens: T,
+    ) -> Vec<(Token, Span)> {
+        let mut output = Vec::new();
+        self.process_lines(split_lines(tokens), 0, &mut output);
+        output
+    }
+
+    fn error(&mut self, description: String, span: Span) {
+        self.messages.push(AssemblerMessage {
+            msg_type: AssemblerMessageType::Error,
+            description,
+            span: Some(span),
+        })
+    }
+
+    /// Processes a list of lines, in which every conditional must be closed
+    fn process_lines(&mut self, lines: Vec<Line>, depth: usize, output: &mut Vec<(Token, Span)>) {
+        let mut lines = lines.into_iter();
+        let mut conditionals: Vec<Conditional> = Vec::new();
+
+        while let Some(line) = lines.next() {
+            if self.ended {
+                break;
+            }
+            let skipping = conditionals
+                .last()
+                .is_some_and(|c| c.state != ConditionalState::Active);
+            let line = if skipping {
+                line
+            } else {
+                let line = self.take_until_end(line);
+                self.remove_malformed(line)
+            };
+            if line.is_empty() {
+                continue;
+            }
+            let (directive, span) = (&line[0].0, line[0].1);
+
+            match directive {
+                Token::If | Token::Ifdef | Token::Ifndef => {
+                    let state = if skipping {
+                        ConditionalState::Done
+                    } else {
+                        self.evaluate_condition(&line)
+                    };
+                    conditionals.push(Conditional {
+                        span,
+                        state,
+                        seen_else: false,
+                    });
+                }
+                Token::Elseif | Token::Else => {
+                    let name = if *directive == Token::Else {
+                        ".else"
+                    } else {
+                        ".elseif"
+                    };
+                    match conditionals.last_mut() {
+                        None => self.error(format!("{} without a matching .if", name), span),
+                        Some(conditional) if conditional.seen_else => self.error(
+                            format!("{} after the .else of this conditional", name),
+                            span,
+                        ),
+                        Some(conditional) => {
+                            conditional.state = match conditional.state {
+                                ConditionalState::Pending if *directive == Token::Else => {
+                                    ConditionalState::Active
+                                }
+                                ConditionalState::Pending => self.evaluate_condition(&line),
+                                _ => ConditionalState::Done,
+                            };
+                            conditional.seen_else = *directive == Token::Else;
+                        }
+                    }
+                }
+                Token::Endif => {
+                    if conditionals.pop().is_none() {
+                        self.error(String::from(".endif without a matching .if"), span)
+                    }
+                }
+                _ if skipping => {}
+                Token::Macro => self.define_macro(line, &mut lines),
+                Token::Include => self.include_file(&line, depth, output),
+                Token::Endm => self.error(String::from(".endm without a matching .macro"), span),
+                Token::Rept | Token::Irp | Token::Irpc => {
+                    self.repeat(line, &mut lines, depth, output)
+                }
+                Token::Endr => self.error(String::from(".endr without a matching .rept"), span),
+                _ => {
+                    if let Some((name, span)) = hyphenated_name(&line) {
+                        self.error(
+                            format!(
+                                "Names can't contain '-', as it's the subtraction operator. Rename {} to {}",
+                                name,
+                                name.replace('-', "_")
+                            ),
+                            span,
+                        );
+                        continue;
+                    }
+                    self.define_symbols(&line);
+                    let start = line_labels(&line).count() * 2;
+                    if let Some((Token::Incbin, _)) = line.get(start) {
+                        self.include_binary(line, start, output);
+                    } else if let Some(start) = self.find_invocation(&line) {
+                        self.expand_macro(line, start, depth, output);
+                    } else {
+                        output.extend(line);
+                    }
+                }
+            }
+        }
+
+        for conditional in conditionals {
+            self.error(
+                String::from("Missing the .endif of this conditional"),
+                conditional.span,
+            );
+        }
+    }
+
+    /// Removes the tokens after the `.end` of a line, if it has one. The source ends there, so
+    /// the code after it isn't read.
+    fn take_until_end(&mut self, mut line: Line) -> Line {
+        if let Some(end) = line.iter().position(|(token, _)| *token == Token::End) {
+            line.truncate(end + 1);
+            self.ended = true;
+        }
+        line
+    }
+
+    /// Reports the tokens of a line that the lexer couldn't read, and removes them
+    fn remove_malformed(&mut self, line: Line) -> Line {
+        line.into_iter()
+            .filter_map(|(token, span)| match token {
+                Token::Malformed(e) => {
+                    self.error(e, span);
+                    None
+                }
+                _ => Some((token, span)),
+            })
+            .collect()
+    }
+
+    /// Processes the lines of the file named by an `.include` line
+    fn include_file(&mut self, line: &Line, depth: usize, output: &mut Vec<(Token, Span)>) {
+        let span = line[0].1;
+        let name = match &line[1..] {
+            [(Token::Str(name), _)] => String::from_utf8_lossy(name).into_owned(),
+            _ => {
+                return self.error(
+                    String::from("Expected the path of the file in quotes after .include"),
+                    span,
+                )
+            }
+        };
+
+        let path = match self.find_file(&name, span) {
+            Some(path) => path,
+            None => return self.error(format!("Can't find the included file {}", name), span),
+        };
+
+        let canonical = canonical_path(&path);
+        if let Some(start) = self.include_stack.iter().position(|(c, _)| *c == canonical) {
+            let cycle: Vec<String> = self.include_stack[start..]
+                .iter()
+                .map(|(_, path)| path)
+                .chain([&path])
+                .map(|path| path.display().to_string())
+                .collect();
+            return self.error(format!("Include cycle: {}", cycle.join(" -> ")), span);
+        }
+
+        let code = match crate::read_source(&path) {
+            Ok(code) => code,
+            Err(e) => return self.error(e, span),
+        };
+        let context = self.source_map.add_context(SpanContext::File {
+            path: path.clone(),
+            code,
+            included_at: Some(span),
+        });
+        let (_, code) = self.source_map.get_file(context);
+        let tokens: Vec<_> = Lexer::new(code).in_context(context).collect();
+
+        self.include_stack.push((canonical, path));
+        self.process_lines(split_lines(tokens), depth, output);
+        self.include_stack.pop();
+    }
+
+    /// Replaces an `.incbin` line, after its labels, by the contents of the file
+    fn include_binary(&mut self, mut line: Line, start: usize, output: &mut Vec<(Token, Span)>) {
+        let arguments = split_arguments(line.split_off(start + 1));
+        let (_, span) = line.pop().unwrap();
+        output.extend(line);
+
+        match self.read_binary(arguments, span) {
+            Ok(data) => {
+                output.push((Token::Incbin, span));
+                output.push((Token::Binary(data), span));
+            }
+            Err((e, span)) => self.error(e, span),
+        }
+    }
+
+    /// Reads the slice of a file given by the `"file" [, offset [, length]]` arguments of `.incbin`
+    fn read_binary(&self, arguments: Vec<Line>, span: Span) -> Result<Vec<u8>, (String, Span)> {
+        let mut arguments = arguments.into_iter();
+        let name = match arguments.next().as_deref() {
+            Some([(Token::Str(name), _)]) => String::from_utf8_lossy(name).into_owned(),
+            _ => {
+                return Err((
+                    String::from("Expected the path of the file in quotes after .incbin"),
+                    span,
+                ))
+            }
+        };
+        let path = self
+            .find_file(&name, span)
+            .ok_or_else(|| (format!("Can't find the binary file {}", name), span))?;
+        let data = fs::read(&path)
+            .map_err(|e| (format!("Error reading {}: {}", path.display(), e), span))?;
+
+        // The offset and length must be known right now, like the conditions of .if
+        let mut bounds = Vec::new();
+        for argument in arguments {
+            let argument_span = argument.first().map_or(span, |(_, span)| *span);
+            let value = expression::evaluate(&argument, span, &self.symbols)?;
+            if value < 0 {
+                return Err((
+                    String::from("The offset and length of .incbin can't be negative"),
+                    argument_span,
+                ));
+            }
+            bounds.push(value as usize);
+        }
+        let (offset, length) = match bounds[..] {
+            [] => (0, data.len()),
+            [offset] => (offset, data.len().saturating_sub(offset)),
+            [offset, length] => (offset, length),
+            _ => {
+                return Err((
+                    String::from("Expected .incbin \"file\" [, offset [, length]]"),
+                    span,
+                ))
+            }
+        };
+
+        if offset + length > data.len() {
+            return Err((
+                format!(
+                    "Can't read {} bytes from offset {} of {}, which has {} bytes",
+                    length,
+                    offset,
+                    name,
+                    data.len()
+                ),
+                span,
+            ));
+        }
+        if length > u16::MAX as usize {
+            return Err((
+                format!("{} bytes of {} don't fit in memory", length, name),
+                span,
+            ));
+        }
+
+        Ok(data[offset..offset + length].to_vec())
+    }
+
+    /// Looks for a file next to the file of the span, and then in the include paths
+    fn find_file(&self, name: &str, span: Span) -> Option<PathBuf> {
+        let (including, _) = self.source_map.get_file(span.context);
+        including
+            .parent()
+            .into_iter()
+            .chain(self.flags.include_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Evaluates the condition of an `.if`, `.ifdef`, `.ifndef` or `.elseif` line
+    fn evaluate_condition(&mut self, line: &Line) -> ConditionalState {
+        let (directive, span) = &line[0];
+        let result = match directive {
+            Token::Ifdef | Token::Ifndef => match &line[1..] {
+                [(Token::Ident(name), _)] => {
+                    Ok(self.symbols.contains_key(name) == (*directive == Token::Ifdef))
+                }
+                _ => Err((String::from("Expected a symbol name"), *span)),
+            },
+            _ => expression::evaluate(&line[1..], *span, &self.symbols).map(|value| value != 0),
+        };
+
+        match result {
+            Ok(true) => ConditionalState::Active,
+            Ok(false) => ConditionalState::Pending,
+            Err((e, span)) => {
+                self.error(e, span);
+                ConditionalState::Done
+            }
+        }
+    }
+
+    /// Records the labels and constants defined by a line, so conditions can refer to them
+    fn define_symbols(&mut self, line: &Line) {
+        for label in line_labels(line) {
+            self.symbols.insert(label.to_string(), None);
+        }
+
+        let (name, value) = match &line[line_labels(line).count() * 2..] {
+            [(Token::Ident(name), _), (Token::Equals, span), value @ ..]
+            | [(Token::Set, _), (Token::Ident(name), _), (Token::Comma, span), value @ ..] => {
+                (name, expression::evaluate(value, *span, &self.symbols).ok())
+            }
+            _ => return,
+        };
+        self.symbols.insert(name.clone(), value);
+    }
+
+    /// Reads the definition of a macro from its header until the matching `.endm`
+    fn define_macro<I: Iterator<Item = Line>>(&mut self, header: Line, lines: &mut I) {
+        let span = header[0].1;
+        let body = take_block(lines, |token| *token == Token::Macro, &Token::Endm);
+
+        let mut header = header.into_iter().skip(1);
+        let name = match header.next() {
+            Some((Token::Ident(name), _)) => name,
+            _ => {
+                return self.error(
+                    String::from("Expected the name of the macro after .macro"),
+                    span,
+                )
+            }
+        };
+        let body = match body {
+            Some(body) => body,
+            None => return self.error(format!("Missing .endm for macro {}", name), span),
+        };
+        if self.macros.contains_key(&name) {
+            return self.error(format!("Macro {} is already defined", name), span);
+        }
+
+        let mut parameters: Vec<MacroParameter> = Vec::new();
+        for group in split_arguments(header.collect()) {
+            let mut group = group.into_iter();
+            let parameter = match (group.next(), group.next()) {
+                (Some((Token::Ident(name), _)), None) => MacroParameter {
+                    name,
+                    default: None,
+                },
+                (Some((Token::Ident(name), _)), Some((Token::Equals, _))) => MacroParameter {
+                    name,
+                    default: Some(group.collect()),
+                },
+                (first, _) => return self.error(
+                    String::from(
+                        "Expected a parameter name, optionally followed by = and its default value",
+                    ),
+                    first.map_or(span, |(_, span)| span),
+                ),
+            };
+            if parameters.iter().any(|p| p.name == parameter.name) {
+                return self.error(
+                    format!("Parameter {} of macro {} is repeated", parameter.name, name),
+                    span,
+                );
+            }
+            parameters.push(parameter);
+        }
+
+        let labels = body
+            .iter()
+            .flat_map(line_labels)
+            .filter(|label| parameters.iter().all(|p| p.name != *label))
+            .map(String::from)
+            .collect();
+
+        self.macros.insert(
+            name,
+            Rc::new(MacroDefinition {
+                parameters,
+                body,
+                labels,
+                context: span.context,
+            }),
+        );
+    }
+
+    /// Returns the position of the macro name if the line invokes a macro
+    fn find_invocation(&self, line: &Line) -> Option<usize> {
+        let start = line_labels(line).count() * 2;
+
+        match &line[start..] {
+            [(Token::Ident(name), _), rest @ ..] => (self.macros.contains_key(name)
+                && !matches!(rest.first(), Some((Token::Equals, _))))
+            .then_some(start),
+            _ => None,
+        }
+    }
+
+    fn expand_macro(
+        &mut self,
+        mut line: Line,
+        start: usize,
+        depth: usize,
+        output: &mut Vec<(Token, Span)>,
+    ) {
+        let arguments = split_arguments(line.split_off(start + 1));
+        let (name, site) = match line.pop() {
+            Some((Token::Ident(name), site)) => (name, site),
+            _ => unreachable!(),
+        };
+        // The labels before the invocation
+        output.extend(line);
+
+        if depth == MAX_MACRO_DEPTH {
+            return self.error(
+                format!(
+                    "Macro invocations are nested more than {} levels deep, is {} recursive?",
+                    MAX_MACRO_DEPTH, name
+                ),
+                site,
+            );
+        }
+
+        let definition = Rc::clone(&self.macros[&name]);
+        if arguments.len() > definition.parameters.len() {
+            return self.error(
+                format!(
+                    "Macro {} takes {} arguments, but {} were given",
+                    name,
+                    definition.parameters.len(),
+                    arguments.len()
+                ),
+                site,
+            );
+        }
+
+        let mut values = HashMap::new();
+        for (i, parameter) in definition.parameters.iter().enumerate() {
+            let value = match (arguments.get(i), &parameter.default) {
+                (Some(argument), _) if !argument.is_empty() => argument.clone(),
+                (_, Some(default)) => default.clone(),
+                _ => {
+                    return self.error(
+                        format!("Missing argument {} of macro {}", parameter.name, name),
+                        site,
+                    )
+                }
+            };
+            values.insert(parameter.name.as_str(), value);
+        }
+
+        let context = self.source_map.add_context(SpanContext::Macro {
+            name,
+            site,
+            body: definition.context,
+        });
+        let expansion = substitute(&definition.body, &values, &definition.labels, context);
+
+        self.process_lines(expansion, depth + 1, output);
+    }
+
+    /// Reads the body of a `.rept`, `.irp` or `.irpc` until the matching `.endr`, and expands it
+    /// once for every iteration
+    fn repeat<I: Iterator<Item = Line>>(
+        &mut self,
+        mut header: Line,
+        lines: &mut I,
+        depth: usize,
+        output: &mut Vec<(Token, Span)>,
+    ) {
+        let (directive, site) = header.remove(0);
+        let directive = match directive {
+            Token::Rept => ".rept",
+            Token::Irp => ".irp",
+            _ => ".irpc",
+        };
+        let body = match take_block(lines, is_repetition, &Token::Endr) {
+            Some(body) => body,
+            None => return self.error(format!("Missing .endr for this {}", directive), site),
+        };
+
+        let (symbol, iterations) = match self.repetition_values(directive, header, site) {
+            Ok(values) => values,
+            Err((e, span)) => return self.error(e, span),
+        };
+        let count = iterations.len();
+        for (i, value) in iterations.into_iter().enumerate() {
+            let context = self.source_map.add_context(SpanContext::Repetition {
+                directive,
+                iteration: i + 1,
+                count,
+                site,
+                body: site.context,
+            });
+            let values = symbol
+                .iter()
+                .map(|symbol| (symbol.as_str(), value.clone()))
+                .collect();
+            let expansion = substitute(&body, &values, &HashSet::new(), context);
+
+            self.process_lines(expansion, depth, output);
+        }
+    }
+
+    /// Returns the symbol replaced in the body of a repetition, if any, and its value in every
+    /// iteration:
+    /// - `.rept COUNT [, COUNTER]` repeats the body COUNT times, with COUNTER going from 0
+    /// - `.irp SYMBOL, VALUE, ...` repeats it for every value
+    /// - `.irpc SYMBOL, "CHARACTERS"` repeats it for every character
+    fn repetition_values(
+        &self,
+        directive: &str,
+        arguments: Line,
+        site: Span,
+    ) -> Result<(Option<String>, Vec<Line>), (String, Span)> {
+        let arguments = split_arguments(arguments);
+        let usage = || {
+            let usage = match directive {
+                ".rept" => ".rept COUNT [, COUNTER]",
+                ".irp" => ".irp SYMBOL, VALUE, ...",
+                _ => ".irpc SYMBOL, \"CHARACTERS\"",
+            };
+            (format!("Expected {}", usage), site)
+        };
+        let symbol = |argument: Option<&Line>| match argument.map(Vec::as_slice) {
+            Some([(Token::Ident(name), span)]) => Ok((name.clone(), *span)),
+            _ => Err(usage()),
+        };
+
+        match directive {
+            ".rept" => {
+                let counter = match arguments.len() {
+                    1 => None,
+                    2 => Some(symbol(arguments.get(1))?),
+                    _ => return Err(usage()),
+                };
+                let count = expression::evaluate(&arguments[0], site, &self.symbols)?;
+                if !(0..=MAX_REPETITIONS).contains(&count) {
+                    return Err((format!("Can't repeat a block {} times", count), site));
+                }
+
+                let values = (0..count)
+                    .map(|i| match &counter {
+                        Some((_, span)) => vec![(Token::Lit(i as u16), *span)],
+                        None => Vec::new(),
+                    })
+                    .collect();
+                Ok((counter.map(|(name, _)| name), values))
+            }
+            ".irp" => {
+                let (symbol, _) = symbol(arguments.first())?;
+                Ok((Some(symbol), arguments[1..].to_vec()))
+            }
+            _ => {
+                let (symbol, _) = symbol(arguments.first())?;
+                let (characters, span) = match arguments.get(1).map(Vec::as_slice) {
+                    Some([(Token::Str(characters), span)]) if arguments.len() == 2 => {
+                        (characters, *span)
+                    }
+                    _ => return Err(usage()),
+                };
+
+                let values = String::from_utf8_lossy(characters)
+                    .chars()
+                    .map(|c| {
+                        Lexer::new(&c.to_string())
+                            .map(|(token, _)| (token, span))
+                            .collect()
+                    })
+                    .collect();
+                Ok((Some(symbol), values))
+            }
+        }
+    }
+}
+
+/// Copies the lines of a body into a new context, replacing the symbols that have a value and
+/// renaming the labels. Lines left empty by the replacement are dropped.
+fn substitute(
+    body: &[Line],
+    values: &HashMap<&str, Line>,
+    labels: &HashSet<String>,
+    context: usize,
+) -> Vec<Line> {
+    body.iter()
+        .map(|line| {
+            line.iter()
+                .flat_map(|(token, span)| {
+                    let span = Span { context, ..*span };
+                    match token {
+                        Token::Ident(name) if values.contains_key(name.as_str()) => {
+                            values[name.as_str()].clone()
+                        }
+                        Token::Ident(name) if labels.contains(name) => {
+                            vec![(Token::Ident(expansion_label(name, context)), span)]
+                        }
+                        _ => vec![(token.clone(), span)],
+                    }
+                })
+                .collect::<Line>()
+        })
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Takes the lines of a block until the line that closes it, skipping the blocks nested inside.
+/// Returns None if the block isn't closed.
+fn take_block<I: Iterator<Item = Line>>(
+    lines: &mut I,
+    opens: fn(&Token) -> bool,
+    close: &Token,
+) -> Option<Vec<Line>> {
+    let mut body = Vec::new();
+    let mut nesting = 0;
+
+    for line in lines.by_ref() {
+        match &line[0].0 {
+            token if opens(token) => nesting += 1,
+            token if token == close && nesting == 0 => return Some(body),
+            token if token == close => nesting -= 1,
+            _ => {}
+        }
+        body.push(line);
+    }
+
+    None
+}
+
+fn is_repetition(token: &Token) -> bool {
+    matches!(token, Token::Rept | Token::Irp | Token::Irpc)
+}
+
+/// Returns the path that identifies a file, no matter how it's reached
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Groups the tokens by the line they were written in
+fn split_lines<T: IntoIterator<Item = (Token, Span)>>(tokens: T) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+
+    for (token, span) in tokens {
+        match lines.last_mut() {
+            Some(line) if line[0].1.lo.line == span.lo.line => line.push((token, span)),
+            _ => lines.push(vec![(token, span)]),
+        }
+    }
+
+    lines
+}
+
+/// Splits a list of arguments by the commas that aren't inside parentheses
+fn split_arguments(tokens: Line) -> Vec<Line> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+
+    let mut arguments = vec![Vec::new()];
+    let mut nesting = 0;
+    for (token, span) in tokens {
+        match token {
+            Token::Comma if nesting == 0 => {
+                arguments.push(Vec::new());
+                continue;
+            }
+            Token::Lpar => nesting += 1,
+            Token::Rpar => nesting -= 1,
+            _ => {}
+        }
+        arguments.last_mut().unwrap().push((token, span));
+    }
+
+    arguments
+}
+
+/// Finds a label or constant defined at the start of the line with a name like `my-label`, which
+/// the lexer reads as a subtraction
+fn hyphenated_name(line: &Line) -> Option<(String, Span)> {
+    let start = line_labels(line).count() * 2;
+    let mut parts = Vec::new();
+    let mut tokens = line[start..].iter();
+
+    loop {
+        match tokens.next()? {
+            (Token::Ident(part), span) => parts.push((part.as_str(), *span)),
+            _ => return None,
+        }
+        match tokens.next()? {
+            (Token::Minus, _) => {}
+            (Token::Colon | Token::Equals, _) if parts.len() > 1 => break,
+            _ => return None,
+        }
+    }
+
+    let (first, last) = (parts[0].1, parts[parts.len() - 1].1);
+    let name: Vec<&str> = parts.iter().map(|(part, _)| *part).collect();
+    Some((
+        name.join("-"),
+        Span {
+            lo: first.lo,
+            hi: last.hi,
+            context: first.context,
+        },
+    ))
+}
+
+/// Returns the names of the labels at the start of the line
+fn line_labels(line: &Line) -> impl Iterator<Item = &str> {
+    line.chunks(2).map_while(|pair| match pair {
+        [(Token::Ident(name), _), (Token::Colon, _)] => Some(name.as_str()),
+        _ => None,
+    })
+}
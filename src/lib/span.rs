@@ -1,17 +1,50 @@
 use std::fmt::{Display, Formatter};
+use std::path;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Span {
     pub lo: FileLoc,
     pub hi: FileLoc,
+    /// Index of the [SpanContext] the span belongs to in the [SourceMap]
+    pub context: usize,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct FileLoc {
     pub line: usize,
     pub col: usize,
 }
 
+/// Where the tokens of a span come from
+pub enum SpanContext {
+    /// Tokens written in a source file, which may have been included at `included_at`
+    File {
+        path: path::PathBuf,
+        code: String,
+        included_at: Option<Span>,
+    },
+    /// Tokens generated by the expansion of a macro invoked at `site`. Their lines refer to the
+    /// macro body, which was written in the `body` context.
+    Macro {
+        name: String,
+        site: Span,
+        body: usize,
+    },
+    /// Tokens generated by an iteration of a `.rept`, `.irp` or `.irpc` at `site`, counting from 1
+    Repetition {
+        directive: &'static str,
+        iteration: usize,
+        count: usize,
+        site: Span,
+        body: usize,
+    },
+}
+
+/// Every context a span can belong to. The main source file is always the first one.
+pub struct SourceMap {
+    contexts: Vec<SpanContext>,
+}
+
 impl Display for Span {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         self.lo.fmt(f)
@@ -23,3 +56,64 @@ impl Display for FileLoc {
         write!(f, "{}:{}", self.line, self.col)
     }
 }
+
+impl SourceMap {
+    pub fn new(path: &path::Path, code: String) -> Self {
+        Self {
+            contexts: vec![SpanContext::File {
+                path: path.to_path_buf(),
+                code,
+                included_at: None,
+            }],
+        }
+    }
+
+    pub fn add_context(&mut self, context: SpanContext) -> usize {
+        self.contexts.push(context);
+        self.contexts.len() - 1
+    }
+
+    pub fn get_context(&self, context: usize) -> &SpanContext {
+        &self.contexts[context]
+    }
+
+    /// Returns the path and the code of the file the lines of a context refer to
+    pub fn get_file(&self, context: usize) -> (&path::Path, &str) {
+        match self.get_context(context) {
+            SpanContext::File { path, code, .. } => (path, code),
+            SpanContext::Macro { body, .. } | SpanContext::Repetition { body, .. } => {
+                self.get_file(*body)
+            }
+        }
+    }
+
+    /// Returns the span of the source file where the code of a span is written, which is the
+    /// outermost macro invocation or repetition for code generated by them
+    pub fn get_file_span(&self, span: Span) -> Span {
+        match self.get_context(span.context) {
+            SpanContext::File { .. } => span,
+            SpanContext::Macro { site, .. } | SpanContext::Repetition { site, .. } => {
+                self.get_file_span(*site)
+            }
+        }
+    }
+
+    /// Returns the contexts of the files included by a line of a file context, in order
+    pub fn get_included_files(&self, context: usize, line: usize) -> Vec<usize> {
+        self.contexts
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| match c {
+                SpanContext::File {
+                    included_at: Some(site),
+                    ..
+                } => {
+                    let site = self.get_file_span(*site);
+                    site.context == context && site.lo.line == line
+                }
+                _ => false,
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
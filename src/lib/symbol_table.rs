@@ -1,39 +1,66 @@
+use crate::span::Span;
 use std::cmp::max;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+/// The bytes of memory that 16-bit addresses reach. Sections can end at this position.
+pub const MEMORY_SIZE: u32 = 0x1_0000;
+
+/// Joins the name of a label defined in a macro body with the expansion it belongs to. The lexer
+/// never produces it, so expanded labels can't clash with the ones written in the source.
+const EXPANSION_SEPARATOR: char = '@';
+
+/// Returns the name that the `label` of a macro body gets in the expansion with the given context
+pub fn expansion_label(label: &str, context: usize) -> String {
+    format!("{}{}{}", label, EXPANSION_SEPARATOR, context)
+}
+
+/// Checks if the symbol is a label renamed by a macro expansion, which outputs don't show
+pub fn is_expansion_label(name: &str) -> bool {
+    name.contains(EXPANSION_SEPARATOR)
+}
 
 pub struct SymbolTable {
     symbols: HashMap<String, SymbolTableEntry>,
+    globals: HashSet<String>,
+    /// The sizes of the .space directives, by the span of their statement
+    space_sizes: HashMap<Span, u32>,
+    /// The spans of the statements that don't fit in memory, which are left out of the program
+    dropped_statements: HashSet<Span>,
     text_section: Section,
     data_section: Section,
 }
 
-struct Section {
+pub struct Section {
     base_address: u16,
-    length: u16,
+    length: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SectionId {
+    Text,
+    Data,
 }
 
 pub struct SymbolTableEntry {
-    value: u16,
+    value: i32,
     is_address: bool,
+    section: Option<SectionId>,
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
         Self {
             symbols: HashMap::new(),
-            data_section: Section {
-                base_address: 0,
-                length: 0,
-            },
-            text_section: Section {
-                base_address: 0,
-                length: 0,
-            },
+            globals: HashSet::new(),
+            space_sizes: HashMap::new(),
+            dropped_statements: HashSet::new(),
+            data_section: Section::new(0, 0),
+            text_section: Section::new(0, 0),
         }
     }
 
-    pub fn put_constant(&mut self, name: String, value: u16) -> Result<(), String> {
+    pub fn put_constant(&mut self, name: String, value: i32) -> Result<(), String> {
         match self.symbols.entry(name) {
             Entry::Vacant(e) => {
                 e.insert(SymbolTableEntry::new_constant(value));
@@ -43,39 +70,94 @@ impl SymbolTable {
         }
     }
 
-    pub fn put_address(&mut self, name: String, value: u16) -> Result<(), String> {
+    pub fn put_address(
+        &mut self,
+        name: String,
+        value: u16,
+        section: SectionId,
+    ) -> Result<(), String> {
         match self.symbols.entry(name) {
             Entry::Vacant(e) => {
-                e.insert(SymbolTableEntry::new_address(value));
+                e.insert(SymbolTableEntry::new_address(value, section));
                 Ok(())
             }
             Entry::Occupied(e) => Err(format!("Symbol {} is already defined", e.key())),
         }
     }
 
+    pub fn put_global(&mut self, name: String) {
+        self.globals.insert(name);
+    }
+
+    /// Checks if the symbol was exported with .global
+    pub fn is_global(&self, symbol: &str) -> bool {
+        self.globals.contains(symbol)
+    }
+
+    /// Returns the symbols exported with .global, sorted by name
+    pub fn get_globals(&self) -> Vec<&str> {
+        let mut globals: Vec<&str> = self.globals.iter().map(|name| name.as_str()).collect();
+        globals.sort();
+        globals
+    }
+
+    pub fn put_space_size(&mut self, span: Span, size: u32) {
+        self.space_sizes.insert(span, size);
+    }
+
+    /// Returns the size of the .space at `span`, as evaluated when the table was built
+    pub fn get_space_size(&self, span: &Span) -> u32 {
+        self.space_sizes[span]
+    }
+
+    pub fn put_dropped_statement(&mut self, span: Span) {
+        self.dropped_statements.insert(span);
+    }
+
+    /// Checks if the statement at `span` was left out because it doesn't fit in memory
+    pub fn is_dropped_statement(&self, span: &Span) -> bool {
+        self.dropped_statements.contains(span)
+    }
+
     pub fn get_symbol(&self, symbol: &str) -> Result<&SymbolTableEntry, String> {
         self.symbols
             .get(symbol)
             .ok_or(format!("Symbol {} isn't defined", symbol))
     }
 
-    pub fn set_text_section(&mut self, base_address: u16, length: u16) {
-        self.text_section = Section {
-            base_address,
-            length,
-        };
+    /// Returns the symbols written in the source, sorted by name, leaving out the labels renamed by
+    /// macro expansions
+    pub fn get_symbols(&self) -> Vec<(&str, &SymbolTableEntry)> {
+        let mut symbols = self.get_all_symbols();
+        symbols.retain(|(name, _)| !is_expansion_label(name));
+        symbols
     }
 
-    pub fn set_data_section(&mut self, base_address: u16, length: u16) {
-        self.data_section = Section {
-            base_address,
-            length,
-        };
+    /// Returns every symbol, sorted by name
+    pub fn get_all_symbols(&self) -> Vec<(&str, &SymbolTableEntry)> {
+        let mut symbols: Vec<(&str, &SymbolTableEntry)> = self
+            .symbols
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry))
+            .collect();
+        symbols.sort_by_key(|(name, _)| *name);
+        symbols
+    }
+
+    pub fn set_text_section(&mut self, base_address: u16, length: u32) {
+        self.text_section = Section::new(base_address, length);
+    }
+
+    pub fn set_data_section(&mut self, base_address: u16, length: u32) {
+        self.data_section = Section::new(base_address, length);
     }
 
+    /// Checks that the sections don't overlap. Empty sections take no memory, so they never do.
     pub fn is_valid_layout(&self) -> bool {
-        self.text_section.get_end_address() <= self.data_section.base_address
-            || self.data_section.get_end_address() <= self.text_section.base_address
+        self.text_section.length == 0
+            || self.data_section.length == 0
+            || self.text_section.get_end_address() <= self.data_section.base_address as u32
+            || self.data_section.get_end_address() <= self.text_section.base_address as u32
     }
 
     pub fn get_text_section_base_address(&self) -> u16 {
@@ -86,7 +168,40 @@ impl SymbolTable {
         self.data_section.base_address
     }
 
-    pub fn get_program_end_address(&self) -> u16 {
+    pub fn get_section(&self, section: SectionId) -> &Section {
+        match section {
+            SectionId::Text => &self.text_section,
+            SectionId::Data => &self.data_section,
+        }
+    }
+
+    pub fn get_text_section(&self) -> &Section {
+        &self.text_section
+    }
+
+    pub fn get_data_section(&self) -> &Section {
+        &self.data_section
+    }
+
+    /// Returns the section that contains the address, if any
+    pub fn get_section_at(&self, address: u16) -> Option<SectionId> {
+        [SectionId::Text, SectionId::Data].into_iter().find(|id| {
+            let section = self.get_section(*id);
+            section.base_address <= address && (address as u32) < section.get_end_address()
+        })
+    }
+
+    /// Returns the non-empty sections, sorted by base address
+    pub fn get_sections(&self) -> Vec<&Section> {
+        let mut sections: Vec<&Section> = [&self.text_section, &self.data_section]
+            .into_iter()
+            .filter(|s| s.length != 0)
+            .collect();
+        sections.sort_by_key(|s| s.base_address);
+        sections
+    }
+
+    pub fn get_program_end_address(&self) -> u32 {
         max(
             self.data_section.get_end_address(),
             self.text_section.get_end_address(),
@@ -95,17 +210,19 @@ impl SymbolTable {
 }
 
 impl SymbolTableEntry {
-    fn new_constant(value: u16) -> Self {
+    fn new_constant(value: i32) -> Self {
         Self {
             value,
             is_address: false,
+            section: None,
         }
     }
 
-    fn new_address(value: u16) -> Self {
+    fn new_address(value: u16, section: SectionId) -> Self {
         Self {
-            value,
+            value: value as i32,
             is_address: true,
+            section: Some(section),
         }
     }
 
@@ -114,12 +231,46 @@ impl SymbolTableEntry {
     }
 
     pub fn get_value(&self) -> u16 {
+        self.value as u16
+    }
+
+    /// Returns the value as used in expressions, where negative constants keep their sign
+    pub fn get_exact_value(&self) -> i32 {
         self.value
     }
+
+    /// Returns the section where the symbol was defined, if it's an address
+    pub fn get_section(&self) -> Option<SectionId> {
+        self.section
+    }
+}
+
+impl SectionId {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            SectionId::Text => ".text",
+            SectionId::Data => ".data",
+        }
+    }
 }
 
 impl Section {
-    fn get_end_address(&self) -> u16 {
-        self.base_address + self.length
+    fn new(base_address: u16, length: u32) -> Self {
+        Self {
+            base_address,
+            length,
+        }
+    }
+
+    pub fn get_base_address(&self) -> u16 {
+        self.base_address
+    }
+
+    pub fn get_length(&self) -> u32 {
+        self.length
+    }
+
+    pub fn get_end_address(&self) -> u32 {
+        self.base_address as u32 + self.length
     }
 }
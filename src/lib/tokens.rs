@@ -1,6 +1,8 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Token {
     Invalid(String),
+    /// A token that was recognised but can't be read, with the reason
+    Malformed(String),
     Ignore,
     And,
     Or,
@@ -31,6 +33,23 @@ pub enum Token {
     Reg(u8),
     Lit(u16),
     Comma,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    ShiftLeft,
+    ShiftRight,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    DoubleEquals,
+    NotEquals,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
     Lpar,
     Rpar,
     Colon,
@@ -39,6 +58,8 @@ pub enum Token {
     Hi,
     BeginText,
     BeginData,
+    /// Starts a lone expression, which the preprocessor parses to evaluate its directives
+    BeginExpression,
     End,
     Byte,
     Word,
@@ -46,18 +67,124 @@ pub enum Token {
     Even,
     Equals,
     Set,
+    Globl,
+    Macro,
+    Endm,
+    If,
+    Ifdef,
+    Ifndef,
+    Elseif,
+    Else,
+    Endif,
+    Include,
+    /// The bytes of a string, with its escape sequences resolved
+    Str(Vec<u8>),
+    Incbin,
+    Rept,
+    Irp,
+    Irpc,
+    Endr,
+    Ascii,
+    Asciz,
+    /// The contents of the file of an .incbin, read by the preprocessor
+    Binary(Vec<u8>),
 }
 
 pub fn parse_reg(tok: &str) -> Token {
     Token::Reg(tok.trim_start_matches('R').parse().unwrap())
 }
 
+/// Parses a decimal, 0x hexadecimal, 0o octal or 0b binary literal, with optional `_` separators
 pub fn parse_int_lit(tok: &str) -> Token {
-    Token::Lit(tok.parse::<i16>().unwrap() as u16)
+    let (radix, name, digits) = match tok.get(..2) {
+        Some("0x" | "0X") => (16, "hexadecimal", &tok[2..]),
+        Some("0o" | "0O") => (8, "octal", &tok[2..]),
+        Some("0b" | "0B") => (2, "binary", &tok[2..]),
+        _ => (10, "decimal", tok),
+    };
+
+    let mut value: u32 = 0;
+    let mut empty = true;
+    for c in digits.chars().filter(|&c| c != '_') {
+        let digit = match c.to_digit(radix) {
+            Some(digit) => digit,
+            None => {
+                return Token::Malformed(format!("Invalid digit {} in {} literal {}", c, name, tok))
+            }
+        };
+        value = value * radix + digit;
+        empty = false;
+        if value > u16::MAX as u32 {
+            return Token::Malformed(format!(
+                "Literal {} doesn't fit in 16 bits, the maximum is 65535",
+                tok
+            ));
+        }
+    }
+
+    if empty {
+        Token::Malformed(format!("Literal {} has no digits", tok))
+    } else {
+        Token::Lit(value as u16)
+    }
 }
 
-pub fn parse_hex_lit(tok: &str) -> Token {
-    Token::Lit(
-        u16::from_str_radix(tok.trim_start_matches("0x").trim_start_matches("0X"), 16).unwrap(),
-    )
+pub fn parse_char_lit(tok: &str) -> Token {
+    let text = &tok[1..tok.len() - 1];
+    match unescape(text) {
+        Ok(bytes) if bytes.len() == 1 => Token::Lit(bytes[0] as u16),
+        Ok(_) if text.chars().count() == 1 => Token::Malformed(format!(
+            "Character {} isn't ASCII, use a \\x escape for its byte",
+            tok
+        )),
+        Ok(_) => Token::Malformed(format!(
+            "Character literal {} must hold a single character",
+            tok
+        )),
+        Err(e) => Token::Malformed(e),
+    }
+}
+
+pub fn parse_str(tok: &str) -> Token {
+    match unescape(&tok[1..tok.len() - 1]) {
+        Ok(bytes) => Token::Str(bytes),
+        Err(e) => Token::Malformed(e),
+    }
+}
+
+/// Returns the bytes of the text of a string or character literal. Characters are encoded in
+/// UTF-8, and the escape sequences are \n, \t, \r, \0, \\, \', \" and \xHH.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some(c @ ('\\' | '\'' | '"')) => c as u8,
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!(
+                        "Escape sequence \\x{} needs two hex digits",
+                        digits
+                    ));
+                }
+                u8::from_str_radix(&digits, 16).unwrap()
+            }
+            Some(c) => return Err(format!("Unknown escape sequence \\{}", c)),
+            None => return Err(String::from("Unfinished escape sequence")),
+        });
+    }
+
+    Ok(bytes)
 }
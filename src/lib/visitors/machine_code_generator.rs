@@ -1,14 +1,18 @@
+use crate::assembler::{Fragment, FragmentKind, Relocation, RelocationField, RelocationKind};
 use crate::nodes::_node_traits::NodeVisitor as nvst;
 use crate::nodes::*;
 use crate::span::Span;
-use crate::symbol_table::SymbolTable;
+use crate::symbol_table::{SectionId, SymbolTable};
 use crate::{AssemblerMessage, AssemblerMessageType, Flags};
 use easy_nodes::Node;
 
 pub struct MachineCodeGenerator<'a> {
     symbol_table: &'a SymbolTable,
-    current_pos: u16,
+    current_pos: u32,
+    current_section: SectionId,
     messages: Vec<AssemblerMessage>,
+    fragments: Vec<Fragment>,
+    relocations: Vec<Relocation>,
     flags: &'a Flags,
 }
 
@@ -18,19 +22,28 @@ enum Rets {
     Instruction(u16),
     RawData(Vec<u8>),
     Reg(u8),
-    Imm(u16),
-    AddressImm(u16),
+    Imm(i32),
+    AddressImm(SymbolAddress),
+    AddressByte(SymbolAddress, RelocationKind),
     #[default]
     Null,
 }
 
+/// An address given as a symbol plus an offset, so it can be relocated
+#[derive(Debug)]
+struct SymbolAddress {
+    symbol: String,
+    section: Option<SectionId>,
+    base: u16,
+    addend: i32,
+}
+
 impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
     fn visit_program(&mut self, _span: &Span, program: &Program) -> Rets {
-        let mut instructions = Vec::new();
-        instructions.resize(self.symbol_table.get_program_end_address() as usize, 0);
+        let mut instructions = vec![0; self.symbol_table.get_program_end_address() as usize];
 
         if let Some(text) = &program.text_section {
-            self.current_pos = self.symbol_table.get_text_section_base_address();
+            self.current_pos = self.symbol_table.get_text_section_base_address() as u32;
 
             let mut pos = self.current_pos;
             text.accept(self).get_raw_contents().iter().for_each(|b| {
@@ -39,7 +52,7 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
             })
         }
         if let Some(data) = &program.data_section {
-            self.current_pos = self.symbol_table.get_data_section_base_address();
+            self.current_pos = self.symbol_table.get_data_section_base_address() as u32;
 
             let mut pos = self.current_pos;
             data.accept(self).get_raw_contents().iter().for_each(|b| {
@@ -53,8 +66,9 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
 
     fn visit_data_section(&mut self, _span: &Span, ds: &DataSection) -> Rets {
         let mut statements = Vec::new();
+        self.current_section = SectionId::Data;
 
-        if self.flags.auto_align_sections && self.current_pos % 2 != 0 {
+        if self.flags.auto_align_sections && !self.current_pos.is_multiple_of(2) {
             self.current_pos += 1;
             statements.push(Rets::RawData(vec![0]));
         }
@@ -69,7 +83,7 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
                 .filter_map(|r| match r {
                     Rets::Instruction(i) => {
                         self.add_warning("Found an instruction on .data", None);
-                        Some(i.to_le_bytes().to_vec())
+                        Some(self.flags.byte_order.to_bytes(*i).to_vec())
                     }
                     Rets::RawData(d) => Some(d.to_vec()),
                     _ => None,
@@ -81,8 +95,9 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
 
     fn visit_text_section(&mut self, _span: &Span, ts: &TextSection) -> Rets {
         let mut statements = Vec::new();
+        self.current_section = SectionId::Text;
 
-        if self.flags.auto_align_sections && self.current_pos % 2 != 0 {
+        if self.flags.auto_align_sections && !self.current_pos.is_multiple_of(2) {
             self.current_pos += 1;
             statements.push(Rets::RawData(vec![0]));
         }
@@ -95,7 +110,7 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
             statements
                 .iter()
                 .filter_map(|r| match r {
-                    Rets::Instruction(i) => Some(i.to_le_bytes().to_vec()),
+                    Rets::Instruction(i) => Some(self.flags.byte_order.to_bytes(*i).to_vec()),
                     Rets::RawData(d) => {
                         self.add_warning("Found raw data in .text!", None);
                         Some(d.to_vec())
@@ -111,12 +126,16 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
         match statement {
             Statement::Instruction(i) => i.accept(self),
             Statement::RawData(r) => r.accept(self),
+            Statement::Label(l) => l.accept(self),
             _ => Default::default(),
         }
     }
 
     fn visit_instruction(&mut self, span: &Span, instruction: &Instruction) -> Rets {
-        let pc = self.current_pos;
+        if self.symbol_table.is_dropped_statement(span) {
+            return Default::default();
+        }
+        let pc = self.current_pos as u16;
         self.current_pos += 2;
 
         match self
@@ -127,27 +146,38 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
                 self.add_error(&e, Some(*span));
                 Default::default()
             }
-            Ok(val) => val,
+            Ok(val) => {
+                self.add_fragment(pc as u32, 2, *span, FragmentKind::Instruction);
+                val
+            }
         }
     }
 
     fn visit_raw_data(&mut self, span: &Span, raw_data: &RawData) -> Rets {
+        if self.symbol_table.is_dropped_statement(span) {
+            return Default::default();
+        }
         match raw_data {
             RawData::WordAlign => {
-                if self.current_pos % 2 == 0 {
+                if self.current_pos.is_multiple_of(2) {
                     Rets::Null
                 } else {
+                    self.add_fragment(self.current_pos, 1, *span, FragmentKind::Data);
                     self.current_pos += 1;
                     Rets::RawData(vec![0])
                 }
             }
             RawData::Bytes(data) => {
-                self.current_pos += raw_data.get_size(self.current_pos);
+                let start = self.current_pos;
+                let size = data.len() as u32;
+                self.add_fragment(start, size, *span, FragmentKind::Data);
+                self.current_pos += size;
 
                 let mut bytes = Vec::new();
-                for node in data {
+                for (i, node) in data.iter().enumerate() {
+                    let address = (start + i as u32) as u16;
                     bytes.push(
-                        node.accept(self)
+                        self.relocatable_value(node, address, RelocationField::Byte)
                             .as_u8()
                             .map_err(|e| self.add_error(&e, Some(*span))),
                     );
@@ -156,21 +186,61 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
                 Rets::RawData(bytes.iter().flatten().copied().collect())
             }
             RawData::Words(data) => {
-                self.current_pos += raw_data.get_size(self.current_pos);
+                let start = self.current_pos;
+                self.current_pos += data.len() as u32 * 2;
 
                 let mut bytes = Vec::new();
 
-                if self.flags.auto_align_words && self.current_pos % 2 != 0 {
+                if self.flags.auto_align_words && !self.current_pos.is_multiple_of(2) {
                     self.current_pos += 1;
                     bytes.push(0);
                 }
 
                 for node in data {
-                    bytes.extend(node.accept(self).as_u16().to_le_bytes());
+                    let address = (start + bytes.len() as u32) as u16;
+                    let word = self
+                        .relocatable_value(node, address, RelocationField::Word)
+                        .as_u16();
+                    bytes.extend(self.flags.byte_order.to_bytes(word));
                 }
 
+                self.add_fragment(start, bytes.len() as u32, *span, FragmentKind::Data);
                 Rets::RawData(bytes)
             }
+            RawData::Space { fill, .. } => {
+                let start = self.current_pos;
+                let size = self.symbol_table.get_space_size(span);
+                self.add_fragment(start, size, *span, FragmentKind::Data);
+                self.current_pos += size;
+
+                let fill_value = fill.accept(self);
+                let byte = match fill_value.as_u8() {
+                    Ok(byte) => byte,
+                    Err(e) => {
+                        self.add_error(&e, Some(*span));
+                        return Default::default();
+                    }
+                };
+                // The byte of an address is relocated in every position it fills
+                if let Rets::AddressByte(target, kind) = &fill_value {
+                    for i in 0..size {
+                        let address = (start + i) as u16;
+                        self.add_relocation(address, RelocationField::Byte, *kind, target);
+                    }
+                }
+
+                Rets::RawData(vec![byte; size as usize])
+            }
+            RawData::Binary(data) => {
+                self.add_fragment(
+                    self.current_pos,
+                    data.len() as u32,
+                    *span,
+                    FragmentKind::Data,
+                );
+                self.current_pos += data.len() as u32;
+                Rets::RawData(data.clone())
+            }
         }
     }
 
@@ -180,15 +250,35 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
 
     fn visit_literal(&mut self, _span: &Span, literal: &Literal) -> Rets {
         match literal {
-            Literal::Constant(c) => Rets::Imm(*c),
+            Literal::Constant(c) => Rets::Imm(*c as i32),
             Literal::SymbolRef(sr) => sr.accept(self),
             Literal::Function(f) => f.accept(self),
+            Literal::Expression(e) => e.accept(self),
         }
     }
 
+    fn visit_label(&mut self, span: &Span, label: &Label) -> Rets {
+        self.add_fragment(
+            self.current_pos,
+            0,
+            *span,
+            FragmentKind::Label(label.label.clone()),
+        );
+        Rets::Null
+    }
+
     fn visit_symbol_ref(&mut self, span: &Span, symbol_ref: &SymbolRef) -> Rets {
         let symbol = match self.symbol_table.get_symbol(&symbol_ref.name) {
             Ok(s) => s,
+            // Relocatable objects leave undefined symbols for the linker
+            Err(_) if self.flags.relocatable => {
+                return Rets::AddressImm(SymbolAddress {
+                    symbol: symbol_ref.name.clone(),
+                    section: None,
+                    base: 0,
+                    addend: 0,
+                })
+            }
             Err(e) => {
                 self.add_error(&e, Some(*span));
                 return Rets::Imm(0);
@@ -196,16 +286,50 @@ impl<'a> NodeVisitor<Rets> for MachineCodeGenerator<'a> {
         };
 
         if symbol.is_address() {
-            Rets::AddressImm(symbol.get_value())
+            Rets::AddressImm(SymbolAddress {
+                symbol: symbol_ref.name.clone(),
+                section: symbol.get_section(),
+                base: symbol.get_value(),
+                addend: 0,
+            })
         } else {
-            Rets::Imm(symbol.get_value())
+            Rets::Imm(symbol.get_exact_value())
         }
     }
 
     fn visit_function(&mut self, _span: &Span, function: &Function) -> Rets {
         match function {
-            Function::Lo(v) => Rets::Imm(v.accept(self).as_u16() & 0xFF),
-            Function::Hi(v) => Rets::Imm(v.accept(self).as_u16() >> 8),
+            Function::Lo(v) => match v.accept(self) {
+                Rets::AddressImm(address) => Rets::AddressByte(address, RelocationKind::Lo),
+                value => Rets::Imm((value.as_u16() & 0xFF) as i32),
+            },
+            Function::Hi(v) => match v.accept(self) {
+                Rets::AddressImm(address) => Rets::AddressByte(address, RelocationKind::Hi),
+                value => Rets::Imm((value.as_u16() >> 8) as i32),
+            },
+        }
+    }
+
+    fn visit_expression(&mut self, span: &Span, expression: &Expression) -> Rets {
+        let result = match expression {
+            Expression::Unary { op, operand } => operand
+                .accept(self)
+                .into_value(self.flags.relocatable)
+                .and_then(|operand| op.apply_value(operand)),
+            Expression::Binary { op, lhs, rhs } => {
+                let lhs = lhs.accept(self).into_value(self.flags.relocatable);
+                let rhs = rhs.accept(self).into_value(self.flags.relocatable);
+                lhs.and_then(|lhs| op.apply_value(lhs, rhs?, self.flags.relocatable))
+            }
+        };
+
+        match result {
+            Ok(Value::Constant(value)) => Rets::Imm(value),
+            Ok(Value::Address(address)) => Rets::AddressImm(address),
+            Err(e) => {
+                self.add_error(&e, Some(*span));
+                Rets::Imm(0)
+            }
         }
     }
 }
@@ -241,7 +365,10 @@ impl<'a> MachineCodeGenerator<'a> {
         Self {
             symbol_table,
             current_pos: 0,
+            current_section: SectionId::Text,
             messages: Vec::new(),
+            fragments: Vec::new(),
+            relocations: Vec::new(),
             flags,
         }
     }
@@ -250,6 +377,80 @@ impl<'a> MachineCodeGenerator<'a> {
         self.messages.clone()
     }
 
+    pub fn get_fragments(&self) -> Vec<Fragment> {
+        self.fragments.clone()
+    }
+
+    fn add_fragment(&mut self, address: u32, length: u32, span: Span, kind: FragmentKind) {
+        self.fragments.push(Fragment {
+            // Only empty statements can start at the end of memory, and they take no address
+            address: address as u16,
+            length,
+            span,
+            kind,
+        });
+    }
+
+    pub fn get_relocations(&self) -> Vec<Relocation> {
+        self.relocations.clone()
+    }
+
+    /// Evaluates a literal, recording a relocation if its value depends on an address
+    fn relocatable_value(
+        &mut self,
+        node: &Node<Span, Literal>,
+        address: u16,
+        field: RelocationField,
+    ) -> Rets {
+        let value = node.accept(self);
+        match &value {
+            Rets::AddressImm(target) => {
+                self.add_relocation(address, field, RelocationKind::Absolute, target)
+            }
+            Rets::AddressByte(target, kind) => self.add_relocation(address, field, *kind, target),
+            _ => (),
+        }
+        value
+    }
+
+    /// Evaluates a branch target, recording a relocation unless it's in the current section
+    fn branch_offset(&mut self, node: &Node<Span, Literal>, pc: u16) -> Result<u8, String> {
+        let target = node.accept(self);
+        match &target {
+            Rets::AddressImm(address) => {
+                if address.get_section() != Some(self.current_section) {
+                    self.add_relocation(
+                        pc,
+                        RelocationField::Imm8,
+                        RelocationKind::PcRelative,
+                        address,
+                    );
+                }
+            }
+            Rets::AddressByte(address, kind) => {
+                self.add_relocation(pc, RelocationField::Imm8, *kind, address)
+            }
+            _ => (),
+        }
+        target.as_u8_relative(pc + 2)
+    }
+
+    fn add_relocation(
+        &mut self,
+        address: u16,
+        field: RelocationField,
+        kind: RelocationKind,
+        target: &SymbolAddress,
+    ) {
+        self.relocations.push(Relocation {
+            address,
+            field,
+            kind,
+            symbol: target.symbol.clone(),
+            addend: target.addend as u16,
+        });
+    }
+
     fn add_warning(&mut self, message: &str, span: Option<Span>) {
         self.messages.push(AssemblerMessage {
             msg_type: AssemblerMessageType::Warning,
@@ -363,31 +564,36 @@ impl<'a> MachineCodeGenerator<'a> {
                 0x2,
                 ra.accept(self).as_u8()?,
                 rd.accept(self).as_u8()?,
-                n6.accept(self).as_u8()?,
+                self.relocatable_value(n6, pc, RelocationField::Imm6)
+                    .as_u8()?,
             ),
             Instruction::Ld { rd, n6, ra } => codify_2r(
                 0x3,
                 ra.accept(self).as_u8()?,
                 rd.accept(self).as_u8()?,
-                n6.accept(self).as_u8()?,
+                self.relocatable_value(n6, pc, RelocationField::Imm6)
+                    .as_u8()?,
             ),
             Instruction::St { n6, ra, rb } => codify_2r(
                 0x4,
                 ra.accept(self).as_u8()?,
                 rb.accept(self).as_u8()?,
-                n6.accept(self).as_u8()?,
+                self.relocatable_value(n6, pc, RelocationField::Imm6)
+                    .as_u8()?,
             ),
             Instruction::Ldb { rd, n6, ra } => codify_2r(
                 0x5,
                 ra.accept(self).as_u8()?,
                 rd.accept(self).as_u8()?,
-                n6.accept(self).as_u8()?,
+                self.relocatable_value(n6, pc, RelocationField::Imm6)
+                    .as_u8()?,
             ),
             Instruction::Stb { n6, ra, rb } => codify_2r(
                 0x6,
                 ra.accept(self).as_u8()?,
                 rb.accept(self).as_u8()?,
-                n6.accept(self).as_u8()?,
+                self.relocatable_value(n6, pc, RelocationField::Imm6)
+                    .as_u8()?,
             ),
             Instruction::Jalr { rd, ra } => {
                 codify_2r(0x7, ra.accept(self).as_u8()?, rd.accept(self).as_u8()?, 0)
@@ -396,37 +602,41 @@ impl<'a> MachineCodeGenerator<'a> {
                 0x8,
                 ra.accept(self).as_u8()?,
                 false,
-                n8.accept(self).as_u8_relative(pc + 2)?,
+                self.branch_offset(n8, pc)?,
             ),
             Instruction::Bnz { ra, n8 } => codify_1r(
                 0x8,
                 ra.accept(self).as_u8()?,
                 true,
-                n8.accept(self).as_u8_relative(pc + 2)?,
+                self.branch_offset(n8, pc)?,
             ),
             Instruction::Movi { rd, n8 } => codify_1r(
                 0x9,
                 rd.accept(self).as_u8()?,
                 false,
-                n8.accept(self).as_u8()?,
+                self.relocatable_value(n8, pc, RelocationField::Imm8)
+                    .as_u8()?,
             ),
             Instruction::Movhi { rd, n8 } => codify_1r(
                 0x9,
                 rd.accept(self).as_u8()?,
                 true,
-                n8.accept(self).as_u8()?,
+                self.relocatable_value(n8, pc, RelocationField::Imm8)
+                    .as_u8()?,
             ),
             Instruction::In { rd, n8 } => codify_1r(
                 0xA,
                 rd.accept(self).as_u8()?,
                 false,
-                n8.accept(self).as_u8()?,
+                self.relocatable_value(n8, pc, RelocationField::Imm8)
+                    .as_u8()?,
             ),
             Instruction::Out { n8, ra } => codify_1r(
                 0xA,
                 ra.accept(self).as_u8()?,
                 true,
-                n8.accept(self).as_u8()?,
+                self.relocatable_value(n8, pc, RelocationField::Imm8)
+                    .as_u8()?,
             ),
             Instruction::Nop => 0xFFFF,
         })
@@ -437,8 +647,9 @@ impl Rets {
     fn as_u8(&self) -> Result<u8, String> {
         match self {
             Rets::Reg(r) => Ok(*r),
-            Rets::Imm(i) => as_u8_lossless(*i),
+            Rets::Imm(i) => as_u8_lossless(*i as u16),
             Rets::AddressImm(_) => Err(String::from("Can't fit an address in a byte!")),
+            Rets::AddressByte(..) => Ok(self.as_u16() as u8),
             Rets::Null => panic!("Attempted to read a NULL value as a u8"),
             x => panic!("Called Rets::as_u8() on an invalid value: {:?}", x),
         }
@@ -447,8 +658,10 @@ impl Rets {
     fn as_u16(&self) -> u16 {
         match self {
             Rets::Reg(r) => *r as u16,
-            Rets::Imm(i) => *i,
-            Rets::AddressImm(i) => *i,
+            Rets::Imm(i) => *i as u16,
+            Rets::AddressImm(address) => address.get_value(),
+            Rets::AddressByte(address, RelocationKind::Hi) => address.get_value() >> 8,
+            Rets::AddressByte(address, _) => address.get_value() & 0xFF,
             Rets::Null => panic!("Attempted to read a NULL value as a u16"),
             x => panic!("Called Rets::as_u16() on an invalid value: {:?}", x),
         }
@@ -456,12 +669,29 @@ impl Rets {
 
     fn as_u8_relative(&self, rel_to: u16) -> Result<u8, String> {
         match self {
-            Rets::Imm(i) => as_u8_lossless(*i),
-            Rets::AddressImm(li) => Ok((*li).wrapping_sub(rel_to).wrapping_div(2) as u8),
+            Rets::Imm(i) => as_u8_lossless(*i as u16),
+            Rets::AddressImm(address) => {
+                Ok(address.get_value().wrapping_sub(rel_to).wrapping_div(2) as u8)
+            }
+            Rets::AddressByte(..) => self.as_u8(),
             x => panic!("Called Rets::as_u8_relative() on an invalid value: {:?}", x),
         }
     }
 
+    /// Returns the value of an operand of an expression. The bytes of an address are only known
+    /// when the program isn't relocatable, as with constants.
+    fn into_value(self, relocatable: bool) -> Result<Value<SymbolAddress>, String> {
+        match self {
+            Rets::Imm(i) => Ok(Value::Constant(i)),
+            Rets::AddressImm(address) => Ok(Value::Address(address)),
+            Rets::AddressByte(..) if relocatable => Err(String::from(
+                "lo() and hi() of an address can't be used in expressions of relocatable objects",
+            )),
+            Rets::AddressByte(..) => Ok(Value::Constant(self.as_u16() as i32)),
+            x => panic!("Called Rets::into_value() on an invalid value: {:?}", x),
+        }
+    }
+
     fn get_raw_contents(self) -> Vec<u8> {
         match self {
             Rets::Raw(i) => i,
@@ -473,6 +703,23 @@ impl Rets {
     }
 }
 
+impl AddressValue for SymbolAddress {
+    fn get_value(&self) -> u16 {
+        (self.base as i32 + self.addend) as u16
+    }
+
+    fn get_section(&self) -> Option<SectionId> {
+        self.section
+    }
+
+    fn with_offset(self, offset: i32) -> Self {
+        Self {
+            addend: self.addend + offset,
+            ..self
+        }
+    }
+}
+
 fn as_u8_lossless(val: u16) -> Result<u8, String> {
     if val <= 255 || (val as i16) == (val as i8) as i16 {
         Ok(val as u8)
@@ -1,13 +1,30 @@
 use crate::assembler::message::{AssemblerMessage, AssemblerMessageType};
 use crate::nodes::_node_traits::NodeVisitor as nvst;
 use crate::nodes::*;
-use crate::symbol_table::SymbolTable;
+use crate::symbol_table::{SectionId, SymbolTable, MEMORY_SIZE};
 use crate::{DataSectionStart, Flags, Span};
 use easy_nodes::Node;
+use std::collections::{HashMap, HashSet};
+
+/// The address of a label, as used in .set and .space expressions
+struct LabelAddress {
+    value: u16,
+    section: SectionId,
+}
+
+/// A constant that depends on symbols that aren't defined yet
+struct PendingConstant {
+    name: String,
+    value: Node<Span, Literal>,
+    span: Span,
+}
 
 pub struct SymbolTableBuilder<'a> {
-    current_pos: u16,
+    current_pos: u32,
+    current_section: SectionId,
     symbol_table: SymbolTable,
+    pending_constants: Vec<PendingConstant>,
+    globals: Vec<(String, Span)>,
     messages: Vec<AssemblerMessage>,
     flags: &'a Flags,
 }
@@ -16,18 +33,198 @@ impl<'a> SymbolTableBuilder<'a> {
     pub fn new(flags: &'a Flags) -> Self {
         Self {
             current_pos: 0,
+            current_section: SectionId::Text,
             symbol_table: SymbolTable::new(),
+            pending_constants: Vec::new(),
+            globals: Vec::new(),
             messages: Vec::new(),
             flags,
         }
     }
 
-    fn put_constant(&mut self, name: String, value: u16) -> Result<(), String> {
+    fn put_constant(&mut self, name: String, value: i32) -> Result<(), String> {
         self.symbol_table.put_constant(name, value)
     }
 
+    fn define_constant(&mut self, name: String, value: &Node<Span, Literal>, span: Span) {
+        if let Err((e, span)) = self.evaluate(value).and_then(|value| {
+            match value {
+                Value::Constant(value) => self.put_constant(name, value),
+                Value::Address(address) => {
+                    self.symbol_table
+                        .put_address(name, address.value, address.section)
+                }
+            }
+            .map_err(|e| (e, span))
+        }) {
+            self.messages.push(AssemblerMessage {
+                msg_type: AssemblerMessageType::Error,
+                description: e,
+                span: Some(span),
+            })
+        }
+    }
+
+    /// Defines the pending constants whose dependencies are known, until no more can be defined.
+    /// Once every label is known, symbols that aren't pending are reported as undefined.
+    fn resolve_pending_constants(&mut self, labels_known: bool) {
+        loop {
+            let pending: HashSet<String> = self
+                .pending_constants
+                .iter()
+                .map(|c| c.name.clone())
+                .collect();
+            let (ready, waiting): (Vec<PendingConstant>, Vec<PendingConstant>) =
+                std::mem::take(&mut self.pending_constants)
+                    .into_iter()
+                    .partition(|c| {
+                        c.value.get_data().get_symbols().iter().all(|s| {
+                            self.symbol_table.get_symbol(s).is_ok()
+                                || (labels_known && !pending.contains(*s))
+                        })
+                    });
+            self.pending_constants = waiting;
+
+            if ready.is_empty() {
+                break;
+            }
+            for constant in ready {
+                self.define_constant(constant.name, &constant.value, constant.span);
+            }
+        }
+    }
+
+    /// Reports the constants left pending, which depend on each other
+    fn report_dependency_cycles(&mut self) {
+        let dependencies: HashMap<&str, Vec<&str>> = self
+            .pending_constants
+            .iter()
+            .map(|c| (c.name.as_str(), c.value.get_data().get_symbols()))
+            .collect();
+        let mut in_cycle: HashSet<&str> = HashSet::new();
+        let mut messages = Vec::new();
+
+        for constant in &self.pending_constants {
+            // Every pending constant depends on another one, so following them finds a cycle
+            let mut path = vec![constant.name.as_str()];
+            let cycle_start = loop {
+                let next = dependencies[path.last().unwrap()]
+                    .iter()
+                    .find(|s| dependencies.contains_key(*s))
+                    .unwrap();
+                if let Some(start) = path.iter().position(|s| s == next) {
+                    break start;
+                }
+                path.push(next);
+            };
+
+            let cycle = &path[cycle_start..];
+            if cycle.contains(&constant.name.as_str()) {
+                // Report each cycle once, from its first constant
+                if !in_cycle.contains(cycle[0]) {
+                    in_cycle.extend(cycle);
+                    messages.push(AssemblerMessage {
+                        msg_type: AssemblerMessageType::Error,
+                        description: format!(
+                            "Constants depend on each other: {} -> {}",
+                            cycle.join(" -> "),
+                            cycle[0]
+                        ),
+                        span: Some(constant.span),
+                    });
+                }
+            } else {
+                messages.push(AssemblerMessage {
+                    msg_type: AssemblerMessageType::Error,
+                    description: format!(
+                        "Constant {} depends on {}, which is part of a dependency cycle",
+                        constant.name, cycle[0]
+                    ),
+                    span: Some(constant.span),
+                });
+            }
+        }
+
+        self.messages.extend(messages);
+    }
+
     fn put_current_address(&mut self, label: String) -> Result<(), String> {
-        self.symbol_table.put_address(label, self.current_pos)
+        match u16::try_from(self.current_pos) {
+            Ok(address) => self
+                .symbol_table
+                .put_address(label, address, self.current_section),
+            Err(_) => Err(format!(
+                "Label {} is past the end of memory, where no address can point",
+                label
+            )),
+        }
+    }
+
+    /// Evaluates an expression that only depends on the symbols defined so far
+    fn evaluate(
+        &self,
+        literal: &Node<Span, Literal>,
+    ) -> Result<Value<LabelAddress>, (String, Span)> {
+        literal.get_data().evaluate(
+            &|sr| {
+                let symbol = self
+                    .symbol_table
+                    .get_symbol(&sr.get_data().name)
+                    .map_err(|e| (e, *sr.get_common()))?;
+                Ok(match symbol.get_section() {
+                    Some(section) => Value::Address(LabelAddress {
+                        value: symbol.get_value(),
+                        section,
+                    }),
+                    None => Value::Constant(symbol.get_exact_value()),
+                })
+            },
+            self.flags.relocatable,
+        )
+    }
+
+    /// Moves the current position past the statement at `span`, of `size` bytes. Statements that
+    /// don't fit in the memory left are reported at `at` and dropped, so the position doesn't change.
+    fn advance(&mut self, span: Span, at: Span, size: usize, directive: &str) {
+        let left = MEMORY_SIZE - self.current_pos;
+        if size <= left as usize {
+            self.current_pos += size as u32;
+        } else {
+            self.messages.push(AssemblerMessage {
+                msg_type: AssemblerMessageType::Error,
+                description: format!(
+                    "This {} of {} bytes doesn't fit in the {} bytes of memory left",
+                    directive, size, left
+                ),
+                span: Some(at),
+            });
+            self.symbol_table.put_dropped_statement(span);
+        }
+    }
+
+    /// Evaluates the size of a .space. Invalid sizes are reported and taken as 0.
+    fn space_size(&mut self, size: &Node<Span, Literal>) -> usize {
+        let result = match self.evaluate(size) {
+            Ok(Value::Constant(value)) if value < 0 => Err((
+                format!("The size of .space can't be negative, but it's {}", value),
+                *size.get_common(),
+            )),
+            Ok(Value::Constant(value)) => Ok(value as usize),
+            Ok(Value::Address(_)) => Err((
+                String::from("The size of .space can't be an address"),
+                *size.get_common(),
+            )),
+            Err(e) => Err(e),
+        };
+
+        result.unwrap_or_else(|(description, span)| {
+            self.messages.push(AssemblerMessage {
+                msg_type: AssemblerMessageType::Error,
+                description,
+                span: Some(span),
+            });
+            0
+        })
     }
 
     #[allow(unused_must_use)] // The result will be used when getting messages
@@ -41,6 +238,18 @@ impl<'a> SymbolTableBuilder<'a> {
                 span: None,
             })
         }
+
+        // Undefined globals are external symbols in relocatable objects
+        for (name, span) in self.globals.drain(..) {
+            if !self.flags.relocatable && self.symbol_table.get_symbol(&name).is_err() {
+                self.messages.push(AssemblerMessage {
+                    msg_type: AssemblerMessageType::Error,
+                    description: format!("Global symbol {} isn't defined", name),
+                    span: Some(span),
+                })
+            }
+            self.symbol_table.put_global(name);
+        }
     }
 
     pub fn get_messages(&self) -> Vec<AssemblerMessage> {
@@ -54,31 +263,46 @@ impl<'a> SymbolTableBuilder<'a> {
 
 impl<'a> NodeVisitor<()> for SymbolTableBuilder<'a> {
     fn visit_program(&mut self, _span: &Span, program: &Program) {
-        self.current_pos = self.flags.text_section_start;
+        // The table is still empty, so these can't clash
+        for (name, value) in &self.flags.defines {
+            self.put_constant(name.clone(), *value).unwrap();
+        }
+        for constant in &program.constants {
+            constant.accept(self);
+        }
+
+        self.current_pos = self.flags.text_section_start as u32;
         let text_start = self.current_pos;
         if let Some(ts) = &program.text_section {
             ts.accept(self);
         }
         self.symbol_table
-            .set_text_section(text_start, self.current_pos - text_start);
+            .set_text_section(text_start as u16, self.current_pos - text_start);
 
         if let DataSectionStart::Absolute(pos) = self.flags.data_section_start {
-            self.current_pos = pos;
+            self.current_pos = pos as u32;
         }
         let data_start = self.current_pos;
         if let Some(ds) = &program.data_section {
             ds.accept(self);
         }
-        self.symbol_table
-            .set_data_section(data_start, self.current_pos - data_start);
+        self.symbol_table.set_data_section(
+            // A .data that starts at the end of memory can only be empty
+            u16::try_from(data_start).unwrap_or(u16::MAX),
+            self.current_pos - data_start,
+        );
 
-        for constant in &program.constants {
-            constant.accept(self);
+        self.resolve_pending_constants(true);
+        self.report_dependency_cycles();
+
+        for global in &program.globals {
+            global.accept(self);
         }
     }
 
     fn visit_data_section(&mut self, _span: &Span, ds: &DataSection) {
-        if self.flags.auto_align_sections && self.current_pos % 2 != 0 {
+        self.current_section = SectionId::Data;
+        if self.flags.auto_align_sections && !self.current_pos.is_multiple_of(2) {
             self.current_pos += 1;
         }
         for statement in &ds.statements {
@@ -87,7 +311,8 @@ impl<'a> NodeVisitor<()> for SymbolTableBuilder<'a> {
     }
 
     fn visit_text_section(&mut self, _span: &Span, ts: &TextSection) {
-        if self.flags.auto_align_sections && self.current_pos % 2 != 0 {
+        self.current_section = SectionId::Text;
+        if self.flags.auto_align_sections && !self.current_pos.is_multiple_of(2) {
             self.current_pos += 1;
         }
         for statement in &ts.statements {
@@ -101,21 +326,35 @@ impl<'a> NodeVisitor<()> for SymbolTableBuilder<'a> {
             Statement::Label(l) => l.accept(self),
             Statement::RawData(r) => r.accept(self),
             Statement::Constant(c) => c.accept(self),
+            Statement::Global(g) => g.accept(self),
         }
     }
 
-    fn visit_instruction(&mut self, _span: &Span, _node: &Instruction) {
-        self.current_pos += 2;
+    fn visit_instruction(&mut self, span: &Span, _node: &Instruction) {
+        self.advance(*span, *span, 2, "instruction");
     }
 
-    fn visit_raw_data(&mut self, _span: &Span, raw_data: &RawData) {
+    fn visit_raw_data(&mut self, span: &Span, raw_data: &RawData) {
         if let RawData::Words(_) = raw_data {
-            if self.flags.auto_align_words && self.current_pos % 2 != 0 {
+            if self.flags.auto_align_words && !self.current_pos.is_multiple_of(2) {
                 self.current_pos += 1;
             }
         }
 
-        self.current_pos += raw_data.get_size(self.current_pos);
+        match (raw_data, raw_data.get_size(self.current_pos)) {
+            (RawData::Space { size, .. }, None) => {
+                // The size must be known right now, so define the constants it may depend on
+                self.resolve_pending_constants(false);
+                let bytes = self.space_size(size);
+                self.advance(*span, *size.get_common(), bytes, ".space");
+                if !self.symbol_table.is_dropped_statement(span) {
+                    self.symbol_table.put_space_size(*span, bytes as u32);
+                }
+            }
+            (RawData::Binary(_), Some(size)) => self.advance(*span, *span, size, ".incbin"),
+            (_, Some(size)) => self.advance(*span, *span, size, "data"),
+            (_, None) => unreachable!(),
+        }
     }
 
     fn visit_label(&mut self, span: &Span, label: &Label) {
@@ -129,17 +368,42 @@ impl<'a> NodeVisitor<()> for SymbolTableBuilder<'a> {
     }
 
     fn visit_constant(&mut self, span: &Span, constant: &Constant) {
-        if let Err(e) = match constant.value.get_data() {
-            Literal::Constant(c) => self.put_constant(constant.name.clone(), *c),
-            _ => Err(String::from(
-                ".set directive only accepts constant values! (Not functions nor labels)",
-            )),
-        } {
-            self.messages.push(AssemblerMessage {
-                msg_type: AssemblerMessageType::Error,
-                description: e,
-                span: Some(*span),
-            })
+        let known = constant
+            .value
+            .get_data()
+            .get_symbols()
+            .iter()
+            .all(|s| self.symbol_table.get_symbol(s).is_ok());
+
+        if known {
+            self.define_constant(constant.name.clone(), &constant.value, *span);
+        } else {
+            self.pending_constants.push(PendingConstant {
+                name: constant.name.clone(),
+                value: constant.value.clone(),
+                span: *span,
+            });
+        }
+    }
+
+    fn visit_global(&mut self, span: &Span, global: &Global) {
+        self.globals.push((global.name.clone(), *span));
+    }
+}
+
+impl AddressValue for LabelAddress {
+    fn get_value(&self) -> u16 {
+        self.value
+    }
+
+    fn get_section(&self) -> Option<SectionId> {
+        Some(self.section)
+    }
+
+    fn with_offset(self, offset: i32) -> Self {
+        Self {
+            value: (self.value as i32 + offset) as u16,
+            ..self
         }
     }
 }
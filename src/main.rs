@@ -1,10 +1,13 @@
-use sisa_assembler::{assemble, DataSectionStart, Flags};
+use sisa_assembler::{assemble, DataSectionStart, Flags, Output, OutputFormat};
 use std::path::PathBuf;
 use std::{env, panic, process, time};
 
 struct Config {
     source_file: PathBuf,
-    output_file: PathBuf,
+    outputs: Vec<(PathBuf, Option<OutputFormat>)>,
+    listing_file: Option<PathBuf>,
+    map_file: Option<PathBuf>,
+    debug_info_file: Option<PathBuf>,
     display_help: bool,
     executable: String,
     flags: Flags,
@@ -34,28 +37,102 @@ The SISA assembler by rdvdev2<me@rdvdev2.com>
 Usage: {} [OPTIONS]
 
 Recognized options:
-    -i, --input FILE                Uses FILE as input (source.S by default)
-    -o, --output FILE               Uses FILE as output (out.bin by default)
+    -i, --input FILE                Uses FILE as input (source.S by default, - for stdin)
+    -o, --output FILE               Uses FILE as output (out.bin, or out.o with -c, by default, - for
+                                    stdout). Can be repeated to write several outputs
+    -f, --format FORMAT             Writes the previous (or next) output in FORMAT, instead of inferring
+                                    it from its extension (.hex, .srec, .s19, .mif, .memh, .vhd, .h, .rs,
+                                    .lst, .map, .json, .o, .obj; bin, or obj with -c, otherwise)
+                                      bin: raw memory image
+                                      sections: one raw file per section (FILE's stem + .text.bin
+                                        and .data.bin) and a manifest of their addresses in FILE
+                                      ihex: Intel HEX
+                                      srec: Motorola S-record (S1/S9)
+                                      mif: Quartus Memory Initialization File
+                                      readmemh: Verilog $readmemh memory file
+                                      vhdl: VHDL package with a ROM constant
+                                      logisim: Logisim Evolution v2.0 raw memory image
+                                      c: C header with the sections and label addresses
+                                      rust: Rust source with the sections and label addresses
+                                      listing: assembly listing
+                                      map: symbol map
+                                      json: debug info mapping addresses to source locations
+                                      hexwords: one word per line, in hexadecimal
+                                      bits: one word per line, in binary
+                                      obj: relocatable object
+    -c, --compile                   Assembles a relocatable object, leaving undefined symbols to the
+                                    linker
+    -D, --define NAME[=VALUE]       Defines the constant NAME as VALUE (1 by default), which can also be
+                                    checked by .if and .ifdef. Can be repeated
+    -I, --include-path DIR          Also looks for the files of .include in DIR, after the directory of
+                                    the file including them. Can be repeated
+    -l, --listing FILE              Also writes an assembly listing to FILE
+    -m, --map FILE                  Also writes a symbol map to FILE
+    -g, --debug-info FILE           Also writes JSON debug info to FILE
 
     --text-section-start ADDRESS    Places the .text section in ADDRESS (0x0000 by default)
     --data-section-start ADDRESS    Places the .data section in ADDRESS (right after .text by default)
     --auto-align-words              Automatically aligns words to multiples of 2 (disabled by default)
     --auto-align-sections           Automatically aligns sections to multiples of 2 (disabled by default)
 
+    --fill-byte BYTE                Fills the gaps between sections with BYTE in bin output (0x00 by
+                                    default)
+    --memory-width BITS             Uses memory cells of BITS bits (8 or 16), addressed by cell (16 for
+                                    mif, readmemh, vhdl, logisim, hexwords and bits, 8 otherwise)
+    --byte-order ORDER              Stores words in little or big endian byte order (little by default)
+    --memory-depth CELLS            Sizes the memory to CELLS cells in mif and vhdl outputs (program size
+                                    by default)
+    --annotate                      Adds the section and labels of each word in hexwords and bits
+                                    outputs (disabled by default)
+
     -h, --help                      Shows this help message", config.executable);
         return Ok(());
     }
 
+    let default_format = if config.flags.relocatable {
+        OutputFormat::Object
+    } else {
+        OutputFormat::Binary
+    };
+    let mut outputs: Vec<Output> = config
+        .outputs
+        .into_iter()
+        .map(|(path, format)| Output {
+            format: format
+                .or_else(|| OutputFormat::from_extension(&path))
+                .unwrap_or(default_format),
+            path,
+        })
+        .collect();
+    if let Some(listing_file) = config.listing_file {
+        outputs.push(Output {
+            path: listing_file,
+            format: OutputFormat::Listing,
+        });
+    }
+    if let Some(map_file) = config.map_file {
+        outputs.push(Output {
+            path: map_file,
+            format: OutputFormat::Map,
+        });
+    }
+    if let Some(debug_info_file) = config.debug_info_file {
+        outputs.push(Output {
+            path: debug_info_file,
+            format: OutputFormat::DebugInfo,
+        });
+    }
+
     eprintln!(
         "{}",
-        assemble(&config.source_file, &config.output_file, &config.flags)?
+        assemble(&config.source_file, &outputs, &config.flags)?
     );
 
     let duration = time::SystemTime::now().duration_since(start);
     if let Ok(duration) = duration {
-        println!("Assembly done in {} ms", duration.as_millis());
+        eprintln!("Assembly done in {} ms", duration.as_millis());
     } else {
-        println!("Assembly done in ?? ms");
+        eprintln!("Assembly done in ?? ms");
     }
 
     Ok(())
@@ -65,12 +142,18 @@ fn parse_arguments() -> Result<Config, String> {
     let mut args = env::args();
     let mut config = Config {
         source_file: PathBuf::from("source.S"),
-        output_file: PathBuf::from("out.bin"),
+        outputs: Vec::new(),
+        listing_file: None,
+        map_file: None,
+        debug_info_file: None,
         display_help: false,
         executable: args.next().unwrap(),
         flags: Default::default(),
     };
 
+    // A --format applies to the previous --output, or to the next one if it's already set
+    let mut pending_format = None;
+
     while let Some(arg) = args.next() {
         match arg.as_ref() {
             "-i" | "--input" => {
@@ -79,12 +162,51 @@ fn parse_arguments() -> Result<Config, String> {
                     .ok_or("Missing a parameter after --input")?
                     .into()
             }
-            "-o" | "--output" => {
-                config.output_file = args
-                    .next()
+            "-o" | "--output" => config.outputs.push((
+                args.next()
                     .ok_or("Missing a parameter after --output")?
-                    .into()
+                    .into(),
+                pending_format.take(),
+            )),
+            "-f" | "--format" => {
+                let format = args
+                    .next()
+                    .ok_or("Missing a parameter after --format")?
+                    .parse()?;
+                match config.outputs.last_mut() {
+                    Some((_, last_format @ None)) => *last_format = Some(format),
+                    _ if pending_format.is_none() => pending_format = Some(format),
+                    _ => return Err(String::from("Found two --format without an --output")),
+                }
+            }
+            "-l" | "--listing" => {
+                config.listing_file = Some(
+                    args.next()
+                        .ok_or("Missing a parameter after --listing")?
+                        .into(),
+                )
+            }
+            "-m" | "--map" => {
+                config.map_file = Some(args.next().ok_or("Missing a parameter after --map")?.into())
+            }
+            "-g" | "--debug-info" => {
+                config.debug_info_file = Some(
+                    args.next()
+                        .ok_or("Missing a parameter after --debug-info")?
+                        .into(),
+                )
+            }
+            "-c" | "--compile" => config.flags.relocatable = true,
+            "-D" | "--define" => {
+                let (name, value) =
+                    parse_define(&args.next().ok_or("Missing a parameter after --define")?)?;
+                config.flags.defines.insert(name, value);
             }
+            "-I" | "--include-path" => config.flags.include_paths.push(
+                args.next()
+                    .ok_or("Missing a parameter after --include-path")?
+                    .into(),
+            ),
             "-h" | "--help" => config.display_help = true,
 
             "--text-section-start" => {
@@ -109,6 +231,44 @@ fn parse_arguments() -> Result<Config, String> {
                 )
             }
 
+            "--fill-byte" => {
+                let byte = args.next().ok_or("Missing a parameter after --fill-byte")?;
+                config.flags.fill_byte = match byte.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => byte.parse(),
+                }
+                .map_err(|e| format!("Error parsing fill byte: {}", e))?
+            }
+
+            "--memory-width" => {
+                config.flags.memory_width = Some(
+                    args.next()
+                        .ok_or("Missing a parameter after --memory-width")?
+                        .parse()?,
+                )
+            }
+
+            "--byte-order" => {
+                config.flags.byte_order = args
+                    .next()
+                    .ok_or("Missing a parameter after --byte-order")?
+                    .parse()?
+            }
+
+            "--memory-depth" => {
+                let depth = args
+                    .next()
+                    .ok_or("Missing a parameter after --memory-depth")?
+                    .parse()
+                    .map_err(|e| format!("Error parsing memory depth: {}", e))?;
+                if depth == 0 {
+                    return Err(String::from("The memory depth must be at least 1 cell"));
+                }
+                config.flags.memory_depth = Some(depth)
+            }
+
+            "--annotate" => config.flags.annotate = true,
+
             "--auto-align-words" => config.flags.auto_align_words = true,
             "--auto-align-sections" => config.flags.auto_align_sections = true,
 
@@ -121,5 +281,42 @@ fn parse_arguments() -> Result<Config, String> {
         }
     }
 
+    if config.outputs.is_empty() {
+        let output = if config.flags.relocatable {
+            "out.o"
+        } else {
+            "out.bin"
+        };
+        config
+            .outputs
+            .push((PathBuf::from(output), pending_format.take()));
+    }
+    if pending_format.is_some() {
+        return Err(String::from("Found a --format without an --output"));
+    }
+
     Ok(config)
 }
+
+/// Parses a NAME[=VALUE] definition, where VALUE is a decimal or 0x prefixed hexadecimal number
+fn parse_define(define: &str) -> Result<(String, i32), String> {
+    let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+
+    let mut chars = name.chars();
+    if !chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!("{} isn't a valid symbol name", name));
+    }
+
+    let value = match value.strip_prefix("0x") {
+        Some(hex) => i32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|e| format!("Error parsing the value of {}: {}", name, e))?;
+    if !(-32768..=65535).contains(&value) {
+        return Err(format!("The value of {} doesn't fit in 16 bits", name));
+    }
+
+    Ok((name.to_string(), value))
+}
@@ -1,3 +1,4 @@
+use std::io::Write;
 use std::path::PathBuf;
 use std::{fs, process};
 
@@ -36,3 +37,987 @@ fn assemble_files() {
         assert!(output.exists());
     }
 }
+
+#[test]
+fn assemble_pipe() {
+    let source = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/test.S");
+    let executable = PathBuf::from(env!("CARGO_BIN_EXE_sas"));
+
+    let result = process::Command::new(executable)
+        .args(["-i", "-", "-o", "-"])
+        .stdin(fs::File::open(source).unwrap())
+        .output()
+        .unwrap();
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout.len(), 0x31);
+    assert_eq!(result.stdout[..2], [0x2C, 0x90]);
+}
+
+#[test]
+fn assemble_multiple_outputs() {
+    let source = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/test.S");
+    let executable = PathBuf::from(env!("CARGO_BIN_EXE_sas"));
+    let tmp_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("multiple_outputs_test");
+
+    fs::create_dir_all(&tmp_dir).unwrap();
+
+    let result = process::Command::new(executable)
+        .current_dir(&tmp_dir)
+        .args(["-i", &source.to_string_lossy()])
+        .args([
+            "-o", "out.bin", "-o", "out.mif", "-o", "out.lst", "-o", "out.map",
+        ])
+        .args(["-o", "out.txt", "--format", "ihex"])
+        .output()
+        .unwrap();
+
+    assert!(result.status.success());
+    assert_eq!(fs::read(tmp_dir.join("out.bin")).unwrap().len(), 0x31);
+    assert!(fs::read_to_string(tmp_dir.join("out.mif"))
+        .unwrap()
+        .starts_with("WIDTH=16;"));
+    assert!(fs::read_to_string(tmp_dir.join("out.lst"))
+        .unwrap()
+        .contains("Symbols:"));
+    assert!(fs::read_to_string(tmp_dir.join("out.map"))
+        .unwrap()
+        .starts_with("# symbol"));
+    assert!(fs::read_to_string(tmp_dir.join("out.txt"))
+        .unwrap()
+        .ends_with(":00000001FF\n"));
+}
+
+#[test]
+fn invalid_options() {
+    let executable = PathBuf::from(env!("CARGO_BIN_EXE_sas"));
+    let options_error = |args: &[&str]| {
+        let result = process::Command::new(&executable)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(!result.status.success());
+        String::from_utf8(result.stderr).unwrap()
+    };
+
+    assert!(options_error(&["--format", "mif", "--memory-depth", "0"])
+        .contains("The memory depth must be at least 1 cell"));
+    assert!(options_error(&["--fill-byte", "0x"]).contains("Error parsing fill byte"));
+    assert!(options_error(&["--fill-byte", "256"]).contains("Error parsing fill byte"));
+    assert!(options_error(&["--format", "vhdl", "--memory-depth", "0"])
+        .contains("The memory depth must be at least 1 cell"));
+}
+
+/// Assembles the source through stdin, writing the output to stdout
+fn assemble_source(source: &str, args: &[&str]) -> process::Output {
+    let executable = PathBuf::from(env!("CARGO_BIN_EXE_sas"));
+
+    let mut child = process::Command::new(executable)
+        .args(["-i", "-", "-o", "-"])
+        .args(args)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn exported_label_collisions() {
+    let source = ".text
+         foo:     NOP
+         FOO:     NOP
+         .end";
+
+    for format in ["c", "rust"] {
+        let result = assemble_source(source, &["--format", format]);
+
+        assert!(!result.status.success());
+        assert!(String::from_utf8(result.stderr)
+            .unwrap()
+            .contains("The labels FOO and foo would both be exported as FOO"));
+    }
+}
+
+#[test]
+fn assemble_object() {
+    let result = assemble_source(
+        ".global main
+         .text
+         main:    MOVI  R0, lo(message)
+                  MOVHI R0, hi(message)
+                  BZ    R1, putc
+                  BNZ   R1, main
+         .data
+         message: .word main, putc
+         .end",
+        &["-c"],
+    );
+
+    assert!(result.status.success());
+    let object = result.stdout;
+    // Header: 8 bytes of .text, 4 of .data, 3 symbols and 5 relocations
+    assert_eq!(
+        object[..16],
+        *b"SOBJ\x01\x00\x08\x00\x04\x00\x03\x00\x05\x00\x08\x90"
+    );
+    // main is global, message is local and putc is an undefined global
+    let symbols = &object[26..];
+    assert_eq!(symbols[..9], *b"\x01\x01\x00\x00\x04main");
+    assert_eq!(symbols[9..21], *b"\x00\x02\x00\x00\x07message");
+    assert_eq!(symbols[21..30], *b"\x01\x00\x00\x00\x04putc");
+    // The branch to putc is relocated, but the one to main isn't
+    let relocations = &symbols[30..];
+    assert_eq!(relocations.len(), 5 * 9);
+    assert_eq!(relocations[18..27], [1, 4, 0, 3, 3, 2, 0, 0, 0]);
+    assert_eq!(relocations[36..45], [2, 2, 0, 1, 0, 2, 0, 0, 0]);
+
+    let result = assemble_source(
+        ".text
+         main:    MOVI  R0, LO_MAIN
+         .set LO_MAIN, lo(main)
+         .end",
+        &["-c"],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr
+        .contains("lo() and hi() of an address can't be used in constants of relocatable objects"));
+    assert!(stderr.contains("<stdin>:3:24"));
+
+    // A .space filled with the byte of an address relocates every byte
+    let result = assemble_source(
+        ".data
+         pad:     .space 3, lo(pad)
+         .end",
+        &["-c"],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout[8..14], [0x03, 0x00, 0x01, 0x00, 0x03, 0x00]);
+}
+
+#[test]
+fn assemble_expressions() {
+    let result = assemble_source(
+        "SIZE = 4
+         IDX = -(1 + 1) * -2
+         .data
+                 .space SIZE*2, 0xFF
+                 .byte SIZE-1, 1 << 3 | 1, (7 % 4) ^ 1, -SIZE
+                 .word 0x1234 >> 4 & 0xFF0, ~0xFF00
+         .text
+                 ADDI R1, R1, SIZE-1
+                 MOVI R0, hi(0x1234 + 2*IDX)
+         .end",
+        &[],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(
+        result.stdout,
+        [
+            0x43, 0x22, 0x12, 0x90, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x03, 0x09,
+            0x02, 0xFC, 0x20, 0x01, 0xFF, 0x00
+        ]
+    );
+}
+
+#[test]
+fn expression_errors() {
+    let result = assemble_source(
+        ".text
+         ADDI R1, R1, 3 / (2 - 2)
+         MOVI R0, 300 * 300 + 1
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Division by zero"));
+    assert!(stderr.contains("<stdin>:2:23"));
+    assert!(stderr.contains("This expression overflows 16 bits"));
+    assert!(stderr.contains("<stdin>:3:19"));
+
+    let result = assemble_source(
+        ".data
+         .space -1
+         .space 40000
+         .space 40000
+         .space UNDEF
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("The size of .space can't be negative, but it's -1"));
+    assert!(stderr.contains("<stdin>:2:17"));
+    assert!(
+        stderr.contains("This .space of 40000 bytes doesn't fit in the 25536 bytes of memory left")
+    );
+    assert!(stderr.contains("<stdin>:4:17"));
+    assert_eq!(stderr.matches("Symbol UNDEF isn't defined").count(), 1);
+
+    let result = assemble_source(
+        ".data
+         my-label: .byte 1
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Rename my-label to my_label"));
+    assert!(stderr.contains("<stdin>:2:10"));
+
+    // The fill of a .space is evaluated once, not once per byte
+    let result = assemble_source(
+        ".data
+         .space 50, 300
+         .space 50, 1/0
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert_eq!(
+        stderr.matches("This value doesn't fit in a byte!").count(),
+        1
+    );
+    assert_eq!(stderr.matches("Division by zero").count(), 1);
+}
+
+#[test]
+fn end_of_memory() {
+    let result = assemble_source(
+        ".text
+         NOP
+         .end",
+        &["--text-section-start", "0xFFFE"],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout[0xFFFE..], [0xFF, 0xFF]);
+
+    let result = assemble_source(
+        ".data
+         .space 0xFFFF
+         .byte 1
+         .end",
+        &[],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout.len(), 0x10000);
+    assert_eq!(result.stdout[0xFFFF], 1);
+
+    let result = assemble_source(
+        ".data
+         .space 0xFFFE
+         .byte 1, 2, 3
+         .word 4
+         end:
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("This data of 3 bytes doesn't fit in the 2 bytes of memory left"));
+    assert!(stderr.contains("<stdin>:3:10"));
+    assert!(stderr.contains("Label end is past the end of memory"));
+    assert!(stderr.contains("<stdin>:5:10"));
+}
+
+#[test]
+fn label_arithmetic() {
+    let result = assemble_source(
+        ".data
+         table:     .word 1, 2, 3
+         end_table:
+         SIZE = end_table - table
+         third = table + 4
+                    .byte end_table - table, lo(table + 1)
+                    .word third, end_table - 2
+         .text
+                    MOVI R0, SIZE
+                    MOVI R1, lo(third - 2)
+                    ADDI R2, R2, end_table - table - 1
+         .end",
+        &["--data-section-start", "0x0010"],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout[..6], [0x06, 0x90, 0x12, 0x92, 0x85, 0x24]);
+    assert_eq!(
+        result.stdout[0x10..],
+        [0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x06, 0x11, 0x14, 0x00, 0x14, 0x00]
+    );
+}
+
+#[test]
+fn label_arithmetic_errors() {
+    let result = assemble_source(
+        ".data
+         t: .word t * 2, t + t
+         .text
+         MOVI R0, -t
+         MOVI R1, t
+         ADDI R2, R2, t + 1
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert_eq!(
+        stderr
+            .matches("Addresses can only be offset by a constant or subtracted from each other")
+            .count(),
+        3
+    );
+    // Addresses need lo() or hi() even if they fit in the immediate
+    assert_eq!(stderr.matches("Can't fit an address in a byte!").count(), 2);
+    assert!(stderr.contains("<stdin>:5:10"));
+    assert!(stderr.contains("<stdin>:6:10"));
+
+    let result = assemble_source(
+        ".text
+         start: NOP
+         .data
+         buf: .word buf - start
+         .end",
+        &["-c"],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Only addresses of the same section can be subtracted"));
+
+    // The bytes of an address can be used in expressions, as in constants, unless they are
+    // relocated
+    let source = ".data
+         t:  .byte lo(t) + 1
+         X = lo(t) + 1
+             .byte X
+         .text
+             MOVI R0, lo(t) | 1
+         .end";
+    let result = assemble_source(source, &[]);
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout, [0x03, 0x90, 0x03, 0x03]);
+
+    let result = assemble_source(source, &["-c"]);
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert_eq!(
+        stderr
+            .matches(
+                "lo() and hi() of an address can't be used in expressions of relocatable objects"
+            )
+            .count(),
+        2
+    );
+    assert!(stderr
+        .contains("lo() and hi() of an address can't be used in constants of relocatable objects"));
+}
+
+#[test]
+fn constant_forward_references() {
+    let result = assemble_source(
+        "TOTAL = COUNT * 2 + 1
+         COUNT = end - start
+         BYTES = lo(start) + hi(end)
+         .text
+         start:  MOVI R0, TOTAL
+                 MOVI R1, COUNT
+         LAST = end - 2
+                 MOVI R2, BYTES
+         end:
+         .data
+                 .space COUNT
+                 .word LAST
+         .end",
+        &[],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(
+        result.stdout,
+        [0x0D, 0x90, 0x06, 0x92, 0x00, 0x94, 0, 0, 0, 0, 0, 0, 0x04, 0x00]
+    );
+}
+
+#[test]
+fn constant_cycles() {
+    let result = assemble_source(
+        "A = B + 1
+         B = C * 2
+         C = A
+         D = C + 1
+         E = E
+         .text
+         NOP
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Constants depend on each other: A -> B -> C -> A"));
+    assert!(stderr.contains("Constant D depends on C, which is part of a dependency cycle"));
+    assert!(stderr.contains("Constants depend on each other: E -> E"));
+}
+
+#[test]
+fn assemble_macros() {
+    let result = assemble_source(
+        ".macro inc reg, amount=1
+             ADDI reg, reg, amount
+         .endm
+         .macro countdown reg, n
+             MOVI reg, n
+         loop:
+             inc reg, -1
+             BNZ reg, loop
+         .endm
+         .text
+         start:  countdown R1, 5
+                 countdown R2, (2 + 1)
+                 inc R3
+         .end",
+        &[],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(
+        result.stdout,
+        [0x05, 0x92, 0x7F, 0x22, 0xFE, 0x83, 0x03, 0x94, 0xBF, 0x24, 0xFE, 0x85, 0xC1, 0x26]
+    );
+
+    // The labels of the expansions can't clash with the source ones, and they aren't exported
+    let result = assemble_source(
+        ".macro wait
+         loop:   BNZ R0, loop
+         .endm
+         .text
+                 wait
+                 wait
+         loop__1:
+         loop__2: NOP
+         .end",
+        &["--format", "map"],
+    );
+
+    assert!(result.status.success());
+    let map = String::from_utf8(result.stdout).unwrap();
+    let labels: Vec<&str> = map
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|name| name.starts_with("loop"))
+        .collect();
+    assert_eq!(labels, ["loop__1", "loop__2"]);
+}
+
+#[test]
+fn macro_errors() {
+    let result = assemble_source(
+        ".macro load reg, value
+             MOVI reg, value
+             MOVHI reg, undefined
+         .endm
+         .macro twice reg
+             load reg, 1
+         .endm
+         .text
+         twice R1
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Symbol undefined isn't defined"));
+    assert!(stderr.contains("<stdin>:3:25"));
+    assert!(stderr.contains("in expansion of macro load"));
+    assert!(stderr.contains("<stdin>:6:14"));
+    assert!(stderr.contains("in expansion of macro twice"));
+    assert!(stderr.contains("<stdin>:9:10"));
+
+    let result = assemble_source(
+        ".macro forever
+             forever
+         .endm
+         .text
+         forever
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Macro invocations are nested more than 16 levels deep"));
+    assert_eq!(stderr.matches("in expansion of macro forever").count(), 2);
+    assert!(stderr.contains("in expansion of macro forever (15 times)"));
+}
+
+#[test]
+fn conditional_assembly() {
+    let source = ".ifndef LEVEL
+         LEVEL = 1
+         .endif
+         .macro zeros n
+         .if n
+                 .byte 0
+                 zeros n-1
+         .endif
+         .endm
+         .data
+         .if LEVEL >> 1
+                 .byte 2
+         .elseif LEVEL
+                 .byte 1
+         .else
+                 .byte 0xFF
+         .endif
+         .ifdef EXTENDED
+                 zeros 3
+         .else
+                 this isn't even valid ~~~
+         .endif
+         .end";
+
+    let result = assemble_source(source, &["-D", "EXTENDED"]);
+    assert!(result.status.success());
+    assert_eq!(result.stdout, [0x01, 0x00, 0x00, 0x00]);
+
+    let result = assemble_source(source, &["-D", "EXTENDED", "-D", "LEVEL=0x2"]);
+    assert!(result.status.success());
+    assert_eq!(result.stdout, [0x02, 0x00, 0x00, 0x00]);
+
+    let result = assemble_source(source, &["--define", "EXTENDED=0", "-D", "LEVEL=0"]);
+    assert!(result.status.success());
+    assert_eq!(result.stdout, [0xFF, 0x00, 0x00, 0x00]);
+
+    let result = assemble_source(source, &[]);
+    assert!(!result.status.success());
+
+    // Only an .end that is assembled ends the source, and nothing after it is read
+    let result = assemble_source(
+        ".data
+         .if 0
+         .end
+         .endif
+                 .byte 1
+         .end    ~~~
+         .if 1
+         .endm",
+        &[],
+    );
+    assert!(result.status.success());
+    assert_eq!(result.stdout, [0x01]);
+}
+
+#[test]
+fn conditional_comparisons() {
+    // Conditions and operands share the grammar, so both must agree on the precedence
+    let result = assemble_source(
+        ".macro check e
+         .if e
+                 .byte 1, e
+         .else
+                 .byte 0, e
+         .endif
+         .endm
+         .data
+                 check 1 + 2 == 3
+                 check 3 > 2 == 1 & 1 < 2
+                 check 1 << 2 < 5
+                 check 2 != 2 | 4 >= 4
+                 check 6 & 3 == 2
+                 check -1 <= 0
+         .end",
+        &[],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout, [1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 1, 1]);
+}
+
+#[test]
+fn conditional_errors() {
+    let result = assemble_source(
+        ".text
+         start:
+         .if start
+         .elseif LATER
+         .endif
+         .else
+         .if 1
+         .else
+         .elseif 1
+         .endif
+         .if 1 2
+         .endif
+         .if 2 *
+         LATER = 2
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("The value of start isn't known at this point"));
+    assert!(stderr.contains("<stdin>:3:14"));
+    assert!(!stderr.contains("LATER"));
+    assert!(stderr.contains(".else without a matching .if"));
+    assert!(stderr.contains(".elseif after the .else of this conditional"));
+    assert!(stderr.contains("Unexpected token after the expression"));
+    assert!(stderr.contains("<stdin>:11:16"));
+    assert!(stderr.contains("expected `Hi`, `Ident`, `Lit`, `Lo`, `Lpar`"));
+    assert!(stderr.contains("Missing the .endif of this conditional"));
+    assert!(stderr.contains("<stdin>:13:10"));
+}
+
+#[test]
+fn assemble_includes() {
+    let tmp_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("includes_test");
+    fs::create_dir_all(tmp_dir.join("lib")).unwrap();
+    fs::write(
+        tmp_dir.join("macros.S"),
+        ".include \"lib/consts.S\"
+         .macro load reg, value
+             MOVI reg, lo(value)
+             MOVHI reg, hi(value)
+         .endm
+         .macro broken reg
+             MOVI reg, UNDEFINED
+         .endm",
+    )
+    .unwrap();
+    fs::write(tmp_dir.join("lib/consts.S"), "WIDTH = 80").unwrap();
+
+    let include_path = tmp_dir.to_string_lossy();
+    let result = assemble_source(
+        ".include \"macros.S\"
+         .text
+         load R1, 0x1234
+         MOVI R2, WIDTH
+         .end",
+        &["-I", &include_path],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout, [0x34, 0x92, 0x12, 0x93, 0x50, 0x94]);
+
+    let result = assemble_source(
+        ".include \"macros.S\"
+         .text
+         broken R1
+         .end",
+        &["-I", &include_path],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Symbol UNDEFINED isn't defined"));
+    assert!(stderr.contains("macros.S:7:24"));
+    assert!(stderr.contains("<stdin>:3:10"));
+
+    fs::write(tmp_dir.join("helper.S"), "helper:\n    MOVI R3, WIDTH").unwrap();
+    let source = ".include \"macros.S\"
+         .text
+         .include \"helper.S\"
+         load R1, 0x1234
+         .end";
+    let args = ["-I", &include_path, "--format"];
+
+    let result = assemble_source(source, &[&args[..], &["json"]].concat());
+    let debug_info = String::from_utf8(result.stdout).unwrap();
+
+    assert!(result.status.success());
+    assert!(debug_info.contains("helper.S\", \"line\": 2, \"column\": 5"));
+    assert!(debug_info.contains("\"file\": \"<stdin>\", \"line\": 4, \"column\": 10"));
+
+    let result = assemble_source(source, &[&args[..], &["listing"]].concat());
+    let listing = String::from_utf8(result.stdout).unwrap();
+
+    assert!(result.status.success());
+    assert!(listing.contains("helper.S\n    1  0000               helper:\n"));
+    assert!(listing.contains("    2  0000  9650             MOVI R3, WIDTH\n"));
+    assert!(listing.contains("; back to <stdin>\n    4  0002  9234  "));
+}
+
+#[test]
+fn include_errors() {
+    let tmp_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("include_errors_test");
+    fs::create_dir_all(&tmp_dir).unwrap();
+    fs::write(tmp_dir.join("a.S"), ".include \"b.S\"").unwrap();
+    fs::write(tmp_dir.join("b.S"), "\n.include \"a.S\"").unwrap();
+
+    let result = assemble_source(
+        ".include \"a.S\"
+         .include \"missing.S\"
+         .include missing
+         .text
+         .end",
+        &["--include-path", &tmp_dir.to_string_lossy()],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("a.S -> "));
+    assert!(stderr.contains("b.S -> "));
+    assert!(stderr.contains("b.S:2:1"));
+    assert!(stderr.contains("in the file included here"));
+    assert!(stderr.contains("Can't find the included file missing.S"));
+    assert!(stderr.contains("Expected the path of the file in quotes after .include"));
+}
+
+#[test]
+fn assemble_incbin() {
+    let tmp_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("incbin_test");
+    fs::create_dir_all(&tmp_dir).unwrap();
+    fs::write(tmp_dir.join("table.bin"), "ABCDEFGH").unwrap();
+    let include_path = tmp_dir.to_string_lossy();
+
+    let result = assemble_source(
+        "OFFSET = 2
+         .data
+         all:    .incbin \"table.bin\"
+         part:   .incbin \"table.bin\", OFFSET, 3
+         tail:   .incbin \"table.bin\", 6
+                 .byte lo(part), lo(tail)
+         .end",
+        &["-I", &include_path],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout, b"ABCDEFGHCDEGH\x08\x0B");
+
+    let result = assemble_source(
+        ".data
+         .incbin \"table.bin\", 6, 3
+         .incbin \"missing.bin\"
+         .incbin \"table.bin\", -1
+         .text
+         .end",
+        &["-I", &include_path],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Can't read 3 bytes from offset 6 of table.bin, which has 8 bytes"));
+    assert!(stderr.contains("Can't find the binary file missing.bin"));
+    assert!(stderr.contains("The offset and length of .incbin can't be negative"));
+
+    fs::write(tmp_dir.join("big.bin"), vec![0; u16::MAX as usize]).unwrap();
+    let result = assemble_source(
+        ".data
+         .space 10
+         big:    .incbin \"big.bin\"
+                 .byte lo(big)
+         .text
+         .end",
+        &["-I", &include_path],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr
+        .contains("This .incbin of 65535 bytes doesn't fit in the 65526 bytes of memory left"));
+    assert!(stderr.contains("<stdin>:3:18"));
+}
+
+#[test]
+fn assemble_repetitions() {
+    let result = assemble_source(
+        ".data
+         squares:
+         .rept 5, i
+                 .word i*i
+         .endr
+         .irp value, 7, lo(squares + 2)
+             .rept 2
+                 .byte value
+             .endr
+         .endr
+         .irpc digit, \"135\"
+                 .byte digit
+         .endr
+         .rept 0
+                 .byte 0xFF
+         .endr
+         .end",
+        &[],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(
+        result.stdout,
+        [
+            0x00, 0x00, 0x01, 0x00, 0x04, 0x00, 0x09, 0x00, 0x10, 0x00, 0x07, 0x07, 0x02, 0x02,
+            0x01, 0x03, 0x05
+        ]
+    );
+
+    // Lines left empty by the substitution are dropped, also inside nested blocks
+    let result = assemble_source(
+        ".data
+         .irp statement, .byte 1,, .byte 2
+             .rept 1
+                 statement
+             .endr
+         .endr
+         .macro repeat_once statement=
+             .rept 1
+                 statement
+             .endr
+         .endm
+         repeat_once
+         repeat_once .byte 3
+         .end",
+        &[],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout, [0x01, 0x02, 0x03]);
+}
+
+#[test]
+fn repetition_errors() {
+    let result = assemble_source(
+        ".data
+         .rept 3, i
+                 .byte 100 / (2 - i)
+         .endr
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Division by zero"));
+    assert!(stderr.contains("in iteration 3 of 3 of this .rept"));
+    assert!(stderr.contains("<stdin>:2:10"));
+    assert!(!stderr.contains("in iteration 1"));
+
+    let result = assemble_source(
+        ".data
+         .rept -1
+         .endr
+         .irpc x, y
+         .endr
+         .endr
+         .irp value, 1, 2
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Can't repeat a block -1 times"));
+    assert!(stderr.contains("Expected .irpc SYMBOL, \"CHARACTERS\""));
+    assert!(stderr.contains(".endr without a matching .rept"));
+    assert!(stderr.contains("Missing .endr for this .irp"));
+}
+
+#[test]
+fn assemble_strings() {
+    let result = assemble_source(
+        "NEWLINE = '\\n'
+         .data
+                 .ascii \"Hi, \\\"you\\\"\\x21\", \"\\t\"
+                 .asciz \"ok\", \"\"
+                 .string \"a\\\\b\"
+                 .byte 'A', 'z' - 'a', NEWLINE, '\\'', '\\0'
+         .text
+                 MOVI R0, 'x'
+         .end",
+        &[],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(
+        result.stdout,
+        b"\x78\x90Hi, \"you\"!\tok\0\0a\\b\0A\x19\n'\0"
+    );
+}
+
+#[test]
+fn string_errors() {
+    let result = assemble_source(
+        ".data
+         .byte 'ab', '\\q'
+         .ascii \"unterminated
+         .if 0
+         .ascii \"\\q is skipped
+         .endif
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Character literal 'ab' must hold a single character"));
+    assert!(stderr.contains("<stdin>:2:16"));
+    assert!(stderr.contains("Unknown escape sequence \\q"));
+    assert!(stderr.contains("<stdin>:2:22"));
+    assert!(stderr.contains("Unterminated string"));
+    assert!(!stderr.contains("<stdin>:5"));
+}
+
+#[test]
+fn numeric_literals() {
+    let result = assemble_source(
+        ".data
+                 .word 65535, 0o17, 0b1010_0101, 1_000
+                 .byte 0x_7F
+         .end",
+        &[],
+    );
+
+    assert!(result.status.success());
+    assert_eq!(result.stdout, b"\xFF\xFF\x0F\x00\xA5\x00\xE8\x03\x7F");
+
+    let result = assemble_source(
+        ".text
+                 MOVI R1, 65536
+                 MOVI R2, 0b102
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("Literal 65536 doesn't fit in 16 bits, the maximum is 65535"));
+    assert!(stderr.contains("<stdin>:2:27"));
+    assert!(stderr.contains("Invalid digit 2 in binary literal 0b102"));
+    assert!(stderr.contains("<stdin>:3:27"));
+
+    // Literals up to 65535 are valid, but still have to fit in their operand
+    let result = assemble_source(
+        ".text
+                 MOVI R0, 40000
+         .end",
+        &[],
+    );
+    let stderr = String::from_utf8(result.stderr).unwrap();
+
+    assert!(!result.status.success());
+    assert!(stderr.contains("This value doesn't fit in a byte!"));
+    assert!(stderr.contains("<stdin>:2:18"));
+}
@@ -0,0 +1,225 @@
+use std::path::PathBuf;
+use std::{fs, process};
+
+fn assemble(name: &str, args: &[&str]) -> Vec<u8> {
+    let source = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/test.S");
+    let executable = PathBuf::from(env!("CARGO_BIN_EXE_sas"));
+    let tmp_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("formats_test");
+    let output = tmp_dir.join(name);
+
+    fs::create_dir_all(&tmp_dir).unwrap();
+
+    let result = process::Command::new(executable)
+        .args([
+            "-i",
+            &source.to_string_lossy(),
+            "-o",
+            &output.to_string_lossy(),
+        ])
+        .args(["--data-section-start", "0x8000"])
+        .args(args)
+        .output()
+        .unwrap();
+
+    assert!(result.status.success());
+    fs::read(output).unwrap()
+}
+
+#[test]
+fn intel_hex() {
+    let hex = String::from_utf8(assemble("test.hex", &["--format", "ihex"])).unwrap();
+    let lines: Vec<&str> = hex.lines().collect();
+
+    assert_eq!(
+        lines.first(),
+        Some(&":10000000089080914A0245229204DA06531405852D")
+    );
+    assert_eq!(lines.last(), Some(&":00000001FF"));
+    assert!(lines.iter().any(|l| l.starts_with(":0D800000")));
+    for line in lines {
+        let bytes: Vec<u8> = (1..line.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)), 0);
+    }
+}
+
+#[test]
+fn s_record() {
+    let srec = String::from_utf8(assemble("test.s19", &["--format", "srec"])).unwrap();
+    let lines: Vec<&str> = srec.lines().collect();
+
+    assert_eq!(lines.first(), Some(&"S0030000FC"));
+    assert_eq!(lines.last(), Some(&"S9030000FC"));
+    assert!(lines.iter().any(|l| l.starts_with("S1108000")));
+    for line in lines {
+        let bytes: Vec<u8> = (2..line.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(bytes[0] as usize, bytes.len() - 1);
+        assert_eq!(bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)), 0xFF);
+    }
+}
+
+#[test]
+fn mif() {
+    let mif = String::from_utf8(assemble("test.mif", &["--format", "mif"])).unwrap();
+
+    assert!(mif.starts_with("WIDTH=16;\nDEPTH=16391;\n"));
+    assert!(mif.contains("\t0000 : 9008;\t-- MOVI  R0, lo(my_vec)    ; Load @my_vec in R0\n"));
+    assert!(mif.contains("\t[0012..4003] : 0;\n"));
+    assert!(mif.ends_with("\t4006 : 0005;\nEND;\n"));
+}
+
+#[test]
+fn readmemh() {
+    let memh = String::from_utf8(assemble("test.memh", &["--format", "readmemh"])).unwrap();
+    let lines: Vec<&str> = memh.lines().collect();
+
+    assert_eq!(lines[0], "@0000");
+    assert_eq!(lines[1], "9008 9180 024A 2245 0492 06DA 1453 8505");
+    assert_eq!(lines[4], "@4000");
+    assert_eq!(lines[5], "0000 0000 0000 0000 0201 0403 0005");
+}
+
+#[test]
+fn vhdl() {
+    let vhdl = String::from_utf8(assemble("test.vhd", &["--format", "vhdl"])).unwrap();
+
+    assert!(vhdl.contains("type rom_t is array (0 to 16390) of std_logic_vector(15 downto 0);"));
+    assert!(vhdl.contains("        0 => x\"9008\",\n"));
+    assert!(vhdl.contains("        16388 => x\"0201\",\n"));
+    assert!(vhdl.contains("        others => x\"0000\"\n"));
+}
+
+#[test]
+fn logisim() {
+    let image = String::from_utf8(assemble("test.img", &["--format", "logisim"])).unwrap();
+    let lines: Vec<&str> = image.lines().collect();
+
+    assert_eq!(lines[0], "v2.0 raw");
+    assert_eq!(lines[1], "9008 9180 24a 2245 492 6da 1453 8505");
+    assert_eq!(lines[3], "ffff 84ff 16370*0 201 403 5");
+}
+
+#[test]
+fn c_header() {
+    let header = String::from_utf8(assemble("test.h", &["--format", "c"])).unwrap();
+
+    assert!(header.contains("#define SISA_DATA_START 0x8000\n"));
+    assert!(header.contains("static const uint8_t sisa_text[] = {\n    0x08, 0x90,"));
+    assert!(header.contains("#define SISA_LABEL_MY_WORD 0x8006\n"));
+}
+
+#[test]
+fn rust_source() {
+    let source = String::from_utf8(assemble("test.rs", &["--format", "rust"])).unwrap();
+
+    assert!(source.contains("pub const TEXT_LENGTH: usize = 0x0024;\n"));
+    assert!(source.contains("pub const DATA: [u8; 13] = [\n"));
+    assert!(source.contains("    pub const MY_VEC: u16 = 0x8008;\n"));
+}
+
+#[test]
+fn sections() {
+    let manifest = String::from_utf8(assemble("sections.txt", &["--format", "sections"])).unwrap();
+    let tmp_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("formats_test");
+
+    assert_eq!(
+        manifest,
+        "# section base length file\n\
+        .text 0x0000 0x0024 sections.text.bin\n\
+        .data 0x8000 0x000d sections.data.bin\n"
+    );
+    assert_eq!(
+        fs::read(tmp_dir.join("sections.text.bin")).unwrap().len(),
+        0x24
+    );
+    assert_eq!(
+        fs::read(tmp_dir.join("sections.data.bin")).unwrap(),
+        [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5]
+    );
+}
+
+#[test]
+fn fill_byte() {
+    let image = assemble("fill.bin", &["--fill-byte", "0xff"]);
+
+    assert_eq!(image.len(), 0x800d);
+    assert_eq!(image[0x23], 0x84);
+    assert!(image[0x24..0x8000].iter().all(|b| *b == 0xff));
+    assert_eq!(image[0x8000], 0);
+
+    let image = assemble("fill_decimal.bin", &["--fill-byte", "255"]);
+    assert!(image[0x24..0x8000].iter().all(|b| *b == 0xff));
+    let image = assemble("fill_zero.bin", &["--fill-byte", "0"]);
+    assert!(image[0x24..0x8000].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn listing() {
+    let listing_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("formats_test/test.lst");
+    assemble(
+        "listing.bin",
+        &["--listing", &listing_file.to_string_lossy()],
+    );
+    let listing = fs::read_to_string(listing_file).unwrap();
+
+    assert!(listing.contains("; section .data (8000 - 800D)\n"));
+    assert!(listing.contains("    8  8008  01 02 03 04  my_vec:     .byte 1, 2, 3, 4, 5\n"));
+    assert!(listing.contains("       800C  05\n"));
+    assert!(listing.contains("   18  000C  1453         while:      CMPEQ R2, R2, R1"));
+    assert!(listing.contains("  my_word                  8006  address\n"));
+}
+
+#[test]
+fn symbol_map() {
+    let map = String::from_utf8(assemble("test.map", &["--format", "map"])).unwrap();
+    let lines: Vec<&str> = map.lines().collect();
+
+    assert_eq!(lines[1], "NULL                     0x0000 constant -");
+    assert_eq!(lines[2], "while                    0x000c address  .text");
+    assert_eq!(lines[4], "my_word                  0x8006 address  .data");
+    assert_eq!(lines[6], "LOOP                     0xffff constant -");
+    assert_eq!(lines[10], ".data                    0x8000 0x000d 0x800d");
+}
+
+#[test]
+fn debug_info() {
+    let json = String::from_utf8(assemble("test.json", &["--format", "json"])).unwrap();
+
+    assert!(json.contains("{\"name\": \".data\", \"start\": 32768, \"end\": 32781}"));
+    assert!(json.contains("{\"name\": \"while\", \"address\": 12, \"section\": \".text\", "));
+    assert!(json
+        .contains("{\"start\": 0, \"end\": 2, \"kind\": \"instruction\", \"span\": {\"file\": "));
+    assert!(json.contains("\"line\": 11, \"column\": 13, \"end_line\": 11, \"end_column\": 33}}"));
+}
+
+#[test]
+fn word_dumps() {
+    let hexwords = String::from_utf8(assemble("test.words", &["--format", "hexwords"])).unwrap();
+    let bits =
+        String::from_utf8(assemble("test.bits", &["--format", "bits", "--annotate"])).unwrap();
+
+    assert!(hexwords.starts_with("0000  9008\n0002  9180\n"));
+    assert!(hexwords.ends_with("800A  0403\n800C  0005\n"));
+    assert!(bits.contains("000C  0001010001010011  .text while:\n"));
+    assert!(bits.ends_with("800C  0000000000000101  .data\n"));
+}
+
+#[test]
+fn byte_order_and_width() {
+    let image = assemble("big.bin", &["--byte-order", "big"]);
+    let hex = String::from_utf8(assemble(
+        "word.hex",
+        &["--format", "ihex", "--memory-width", "16"],
+    ))
+    .unwrap();
+
+    assert_eq!(image[..2], [0x90, 0x08]);
+    assert_eq!(image[0x8008..], [1, 2, 3, 4, 5]);
+    assert!(hex.starts_with(":10000000900891800"));
+    assert!(hex.contains("\n:0E400000"));
+}